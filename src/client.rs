@@ -1,28 +1,315 @@
-//! An HTTP client that passes cookies back and forth between our clients and pickleballtournaments.com
+//! An HTTP client that passes cookies back and forth between our clients and pickleballtournaments.com,
+//! or whichever mirror of it is configured as the upstream
 
-use std::convert::{Infallible, TryFrom};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use http::Error as HttpError;
 use reqwest::cookie::{CookieStore, Jar};
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue, IntoHeaderName};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, IntoHeaderName, COOKIE, SET_COOKIE};
 use reqwest::redirect::Policy;
 use reqwest::{
-    Client as ReqwestClient, Error, IntoUrl, RequestBuilder as ReqwestRequestBuilder, Response, Url,
+    Client as ReqwestClient, Error as ReqwestError, IntoUrl, Request as ReqwestRequest,
+    RequestBuilder as ReqwestRequestBuilder, Response as ReqwestResponse, StatusCode, Url,
 };
-use rocket::http::{Cookie, CookieJar};
-use rocket::outcome::try_outcome;
+use rocket::fairing::AdHoc;
+use rocket::http::{Cookie, CookieJar, Status};
 use rocket::request::{FromRequest, Outcome, Request};
-use rocket::serde::Serialize;
+use rocket::serde::{Deserialize, Serialize};
 
+use crate::util::metrics::METRICS;
+use crate::util::rate_limit::{RateLimitPermit, RateLimiter};
+use crate::util::session_store::SessionStore;
+
+/// The origin that every scraped page is fetched from
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    origin: Url,
+}
+
+impl Upstream {
+    pub fn new(origin: Url) -> Self {
+        Self { origin }
+    }
+
+    /// Reads `upstream_url` from the Rocket configuration and puts the upstream in managed state
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Upstream", |rocket| async {
+            let origin = match rocket.figment().extract_inner::<String>("upstream_url") {
+                Ok(origin) => origin,
+                Err(error) if error.missing() => DEFAULT_UPSTREAM_URL.to_owned(),
+                Err(error) => {
+                    error!("invalid upstream url: {}", error);
+                    return Err(rocket);
+                }
+            };
+
+            match Url::parse(&origin) {
+                Ok(origin) if origin.has_host() && origin.scheme().starts_with("http") => {
+                    Ok(rocket.manage(Upstream::new(origin)))
+                }
+                _ => {
+                    error!("upstream url must be an http(s) url: {:?}", origin);
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    /// The absolute URL of `path` on the upstream
+    pub fn url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.origin.as_str().trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    pub fn origin(&self) -> &Url {
+        &self.origin
+    }
+
+    /// The value of the Host header for requests to the upstream
+    fn host(&self) -> String {
+        let host = self.origin.host_str().unwrap_or_default();
+
+        match self.origin.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_owned(),
+        }
+    }
+}
+
+const DEFAULT_UPSTREAM_URL: &str = "https://www.pickleballtournaments.com";
+
+/// Which kind of page a request is for, since each kind gets its own timeouts
+#[derive(Clone, Copy, Debug)]
+pub enum RequestClass {
+    /// The tournament listings, which are large and slow to generate
+    Listing,
+    /// Tournament info, event lists and player lists
+    Tournament,
+    /// Event brackets, which visitors wait on the most during a tournament
+    Bracket,
+}
+
+/// Seconds to wait on the upstream
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct Timeouts {
+    /// How long to wait for a new connection.  Connections are shared between every kind of request,
+    /// so this isn't set per request class.
+    pub connect: u64,
+    pub listing: RequestTimeouts,
+    pub tournament: RequestTimeouts,
+    pub bracket: RequestTimeouts,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RequestTimeouts {
+    /// How long to wait for the upstream to start responding
+    pub read: u64,
+    /// How long the whole request may take, including reading the page
+    pub total: u64,
+}
+
+impl Timeouts {
+    fn validate(&self) -> Result<(), String> {
+        if self.connect == 0 {
+            return Err("connect must be at least 1 second".to_owned());
+        }
+
+        for (name, timeouts) in [
+            ("listing", self.listing),
+            ("tournament", self.tournament),
+            ("bracket", self.bracket),
+        ] {
+            if timeouts.read == 0 || timeouts.read > timeouts.total {
+                return Err(format!(
+                    "{} read must be at least 1 second and no more than the total",
+                    name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn for_class(&self, class: RequestClass) -> RequestTimeouts {
+        match class {
+            RequestClass::Listing => self.listing,
+            RequestClass::Tournament => self.tournament,
+            RequestClass::Bracket => self.bracket,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: 10,
+            listing: RequestTimeouts {
+                read: 30,
+                total: 60,
+            },
+            tournament: RequestTimeouts {
+                read: 20,
+                total: 45,
+            },
+            bracket: RequestTimeouts {
+                read: 10,
+                total: 20,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The upstream didn't start responding within the read timeout
+    Timeout(Url),
+    Request(ReqwestError),
+}
+
+impl Error {
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::Timeout(_) => true,
+            Error::Request(error) => error.is_timeout(),
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Timeout(_) => None,
+            Error::Request(error) => error.status(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout(url) => write!(f, "timed out waiting on {}", url),
+            Error::Request(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<ReqwestError> for Error {
+    fn from(error: ReqwestError) -> Self {
+        Error::Request(error)
+    }
+}
+
+/// Clients share one pool of connections to the upstream.  The one in managed state uses the server's
+/// own session, and each incoming request gets a copy of it with that visitor's cookies instead.
+#[derive(Clone)]
 pub struct Client<'r> {
     outgoing_cookies: Option<&'r CookieJar<'r>>,
+    cookies: Arc<dyn CookieStore>,
+    session: Option<SessionStore>,
     client: ReqwestClient,
+    rate_limiter: Option<RateLimiter>,
+    timeouts: Timeouts,
+    upstream: Upstream,
+}
+
+impl Client<'static> {
+    /// Builds the shared client once the upstream, rate limiter and session are managed, and puts it in
+    /// managed state
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Upstream Client", |rocket| async {
+            let upstream = match rocket.state::<Upstream>() {
+                Some(upstream) => upstream.clone(),
+                None => {
+                    error!("the upstream client needs the upstream to be managed");
+                    return Err(rocket);
+                }
+            };
+
+            let timeouts = match rocket
+                .figment()
+                .extract_inner::<Timeouts>("upstream_timeouts")
+            {
+                Ok(timeouts) => timeouts,
+                Err(error) if error.missing() => Timeouts::default(),
+                Err(error) => {
+                    error!("invalid upstream timeouts: {}", error);
+                    return Err(rocket);
+                }
+            };
+
+            if let Err(error) = timeouts.validate() {
+                error!("invalid upstream timeouts: {}", error);
+                return Err(rocket);
+            }
+
+            let mut client_builder = ClientBuilder::new(upstream).timeouts(timeouts);
+
+            if let Some(rate_limiter) = rocket.state::<RateLimiter>() {
+                client_builder = client_builder.rate_limiter(rate_limiter.clone());
+            }
+
+            if let Some(session) = rocket.state::<SessionStore>() {
+                client_builder = client_builder.session(session.clone());
+            }
+
+            let client = client_builder.build();
+
+            Ok(rocket.manage(client))
+        })
+    }
 }
 
 impl<'r> Client<'r> {
-    pub fn get<U>(&self, url: U) -> RequestBuilder
+    /// A client that shares this one's connections, but sends `cookies` upstream and passes any
+    /// cookies set by the upstream back into them
+    pub fn with_cookies<'c>(&self, cookies: &'c CookieJar<'c>) -> Client<'c> {
+        let cookie_values = cookies
+            .iter()
+            .map(|c| HeaderValue::from_str(&c.to_string()).unwrap())
+            .collect::<Vec<_>>();
+
+        let client_cookies = Jar::default();
+        client_cookies.set_cookies(&mut cookie_values.iter(), &self.upstream.origin);
+
+        Client {
+            outgoing_cookies: Some(cookies),
+            cookies: Arc::new(client_cookies),
+            session: None,
+            client: self.client.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            timeouts: self.timeouts,
+            upstream: self.upstream.clone(),
+        }
+    }
+
+    /// A client that keeps the same connection and incoming cookies, but can outlive the request it came from.
+    /// Cookies set by its responses are not passed back to our client.
+    pub fn detached(&self) -> Client<'static> {
+        Client {
+            outgoing_cookies: None,
+            cookies: self.cookies.clone(),
+            session: self.session.clone(),
+            client: self.client.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            timeouts: self.timeouts,
+            upstream: self.upstream.clone(),
+        }
+    }
+
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    pub fn get<U>(&self, url: U) -> RequestBuilder<'_>
     where
         U: IntoUrl,
     {
@@ -31,11 +318,13 @@ impl<'r> Client<'r> {
         RequestBuilder {
             client: self,
             request,
+            idempotent: true,
+            class: RequestClass::Tournament,
         }
     }
 
     #[allow(dead_code)]
-    pub fn post<U>(&self, url: U) -> RequestBuilder
+    pub fn post<U>(&self, url: U) -> RequestBuilder<'_>
     where
         U: IntoUrl,
     {
@@ -44,20 +333,24 @@ impl<'r> Client<'r> {
         RequestBuilder {
             client: self,
             request,
+            idempotent: false,
+            class: RequestClass::Tournament,
         }
     }
 }
 
-pub struct ClientBuilder<'r> {
-    cookies: Option<&'r CookieJar<'r>>,
-    incoming_cookies_url: Option<Url>,
+pub struct ClientBuilder {
+    upstream: Upstream,
     default_headers: HeaderMap,
     redirect_policy: Option<Policy>,
+    rate_limiter: Option<RateLimiter>,
+    session: Option<SessionStore>,
+    timeouts: Timeouts,
 }
 
-impl<'r> ClientBuilder<'r> {
-    pub fn new() -> Self {
-        let default_headers = std::array::IntoIter::new([
+impl ClientBuilder {
+    pub fn new(upstream: Upstream) -> Self {
+        let mut default_headers = IntoIterator::into_iter([
             ("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8"),
             ("Accept-Language", "en-US,en;q=0.5"),
             ("Connection", "keep-alive"),
@@ -79,20 +372,19 @@ impl<'r> ClientBuilder<'r> {
         })
         .collect::<HeaderMap<_>>();
 
+        default_headers.insert("Host", upstream.host().parse().unwrap());
+
         Self {
-            cookies: None,
-            incoming_cookies_url: None,
+            upstream,
             default_headers,
             redirect_policy: None,
+            rate_limiter: None,
+            session: None,
+            timeouts: Timeouts::default(),
         }
     }
 
-    pub fn forward_cookies(mut self, cookies: &'r CookieJar<'r>, from_url: Url) -> Self {
-        self.cookies = Some(cookies);
-        self.incoming_cookies_url = Some(from_url);
-        self
-    }
-
+    #[allow(dead_code)]
     pub fn default_header<K>(mut self, key: K, value: &str) -> Self
     where
         K: IntoHeaderName,
@@ -107,29 +399,47 @@ impl<'r> ClientBuilder<'r> {
         self
     }
 
-    pub fn build(self) -> Client<'r> {
-        let mut client_builder = ReqwestClient::builder().default_headers(self.default_headers);
+    /// Sends every request through `rate_limiter`, retrying GETs that fail in passing
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
 
-        let client_cookies = Arc::new(Jar::default());
-        if let Some(cookies) = self.cookies {
-            let cookie_values = cookies
-                .iter()
-                .map(|c| HeaderValue::from_str(&c.to_string()).unwrap())
-                .collect::<Vec<_>>();
-            client_cookies.set_cookies(
-                &mut cookie_values.iter(),
-                self.incoming_cookies_url.as_ref().unwrap(),
-            );
-        }
-        client_builder = client_builder.cookie_provider(client_cookies);
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Keeps the client's cookies in `session`, and tells it whether the upstream accepted them
+    pub fn session(mut self, session: SessionStore) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Builds a client with the session's cookies, if it has one.  Use [`Client::with_cookies`] to give
+    /// copies of it a visitor's cookies instead.
+    pub fn build(self) -> Client<'static> {
+        let mut client_builder = ReqwestClient::builder()
+            .default_headers(self.default_headers)
+            .connect_timeout(Duration::from_secs(self.timeouts.connect));
 
         if let Some(policy) = self.redirect_policy {
             client_builder = client_builder.redirect(policy)
         }
 
+        let cookies: Arc<dyn CookieStore> = match &self.session {
+            Some(session) => Arc::new(session.clone()),
+            None => Arc::new(Jar::default()),
+        };
+
         Client {
-            outgoing_cookies: self.cookies,
+            outgoing_cookies: None,
+            cookies,
+            session: self.session,
             client: client_builder.build().unwrap(),
+            rate_limiter: self.rate_limiter,
+            timeouts: self.timeouts,
+            upstream: self.upstream,
         }
     }
 }
@@ -137,6 +447,9 @@ impl<'r> ClientBuilder<'r> {
 pub struct RequestBuilder<'r> {
     client: &'r Client<'r>,
     request: ReqwestRequestBuilder,
+    /// Whether the request can safely be sent again if it fails
+    idempotent: bool,
+    class: RequestClass,
 }
 
 impl<'r> RequestBuilder<'r> {
@@ -148,8 +461,8 @@ impl<'r> RequestBuilder<'r> {
         <HeaderValue as TryFrom<V>>::Error: Into<HttpError>,
     {
         Self {
-            client: self.client,
             request: self.request.header(key, value),
+            ..self
         }
     }
 
@@ -159,43 +472,189 @@ impl<'r> RequestBuilder<'r> {
         T: Serialize + ?Sized,
     {
         Self {
-            client: self.client,
             request: self.request.form(form),
+            ..self
         }
     }
 
+    /// Picks the timeouts for the request.  Requests are for tournament pages unless told otherwise.
+    pub fn class(self, class: RequestClass) -> Self {
+        Self { class, ..self }
+    }
+
     pub async fn send(self) -> Result<Response, Error> {
-        let response = self.request.send().await;
+        let timeouts = self.client.timeouts.for_class(self.class);
+        let read_timeout = Duration::from_secs(timeouts.read);
+
+        let mut request = self
+            .request
+            .timeout(Duration::from_secs(timeouts.total))
+            .build()?;
+
+        // The shared connection pool doesn't keep cookies, so they're added and collected here.
+        // Cookies set by redirects along the way aren't seen.
+        if let Some(cookies) = self.client.cookies.cookies(request.url()) {
+            request.headers_mut().insert(COOKIE, cookies);
+        }
+
+        let url = request.url().clone();
+        let start = Instant::now();
+
+        let response: Result<Response, Error> = match &self.client.rate_limiter {
+            Some(rate_limiter) => {
+                send_limited(
+                    &self.client.client,
+                    request,
+                    read_timeout,
+                    self.idempotent,
+                    rate_limiter,
+                )
+                .await
+            }
+            None => execute(&self.client.client, request, read_timeout)
+                .await
+                .map(|response| Response {
+                    response,
+                    request_url: url.clone(),
+                    _permit: None,
+                }),
+        };
+
+        let outcome = match &response {
+            Ok(response) => response.status().as_u16().to_string(),
+            Err(error) if error.is_timeout() => "timeout".to_owned(),
+            Err(_) => "error".to_owned(),
+        };
+        METRICS.record_upstream_request(&url, &outcome, start.elapsed());
 
         if let Ok(ref response) = response {
+            let set_cookies = response.headers().get_all(SET_COOKIE);
+
+            self.client
+                .cookies
+                .set_cookies(&mut set_cookies.iter(), response.url());
+
             if let Some(outgoing_cookies) = self.client.outgoing_cookies {
                 // Copy any new cookies into the outgoing jar
-                for header in response.headers().get_all("set-cookie").iter() {
+                for header in set_cookies.iter() {
                     outgoing_cookies
                         .add(Cookie::parse(String::from(header.to_str().unwrap())).unwrap());
                 }
             }
+
+            if let Some(session) = &self.client.session {
+                if is_captcha(response) {
+                    session.record(true).await;
+                } else if response.status().is_success() {
+                    session.record(false).await;
+                }
+            }
         }
 
         response
     }
 }
 
+/// A response from the upstream.  When requests are rate limited, it keeps its place among the
+/// requests in flight until it's dropped, so that reading the body counts against the limit too.
+pub struct Response {
+    response: ReqwestResponse,
+    request_url: Url,
+    _permit: Option<RateLimitPermit>,
+}
+
+impl Response {
+    /// The URL that was asked for, before any redirects
+    pub fn request_url(&self) -> &Url {
+        &self.request_url
+    }
+}
+
+impl Deref for Response {
+    type Target = ReqwestResponse;
+
+    fn deref(&self) -> &Self::Target {
+        &self.response
+    }
+}
+
+impl DerefMut for Response {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.response
+    }
+}
+
+/// Whether the upstream sent us off to solve a captcha instead of answering
+pub fn is_captcha(response: &ReqwestResponse) -> bool {
+    response.url().host_str() == Some("validate.perfdrive.com")
+}
+
+async fn execute(
+    client: &ReqwestClient,
+    request: ReqwestRequest,
+    read_timeout: Duration,
+) -> Result<ReqwestResponse, Error> {
+    let url = request.url().clone();
+
+    match async_std::future::timeout(read_timeout, client.execute(request)).await {
+        Ok(response) => Ok(response?),
+        Err(_) => Err(Error::Timeout(url)),
+    }
+}
+
+async fn send_limited(
+    client: &ReqwestClient,
+    request: ReqwestRequest,
+    read_timeout: Duration,
+    idempotent: bool,
+    rate_limiter: &RateLimiter,
+) -> Result<Response, Error> {
+    let mut request = request;
+    let request_url = request.url().clone();
+    let mut attempt = 0;
+
+    loop {
+        let retry_request = if idempotent && attempt < rate_limiter.limits().max_retries {
+            request.try_clone()
+        } else {
+            None
+        };
+
+        let permit = rate_limiter.acquire().await;
+        let response = execute(client, request, read_timeout)
+            .await
+            .map(|response| Response {
+                response,
+                request_url: request_url.clone(),
+                _permit: Some(permit),
+            });
+
+        let should_retry = match &response {
+            Ok(response) => response.status().is_server_error(),
+            Err(error) => error.is_timeout(),
+        };
+
+        match retry_request {
+            Some(retry_request) if should_retry => {
+                // Give up the permit while waiting to retry
+                drop(response);
+                attempt += 1;
+                async_std::task::sleep(rate_limiter.retry_delay(attempt)).await;
+                request = retry_request;
+            }
+            _ => return response,
+        }
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for Client<'r> {
-    type Error = Infallible;
+    type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let incoming_cookies = try_outcome!(request.guard::<&CookieJar<'_>>().await);
-
-        let client = ClientBuilder::new()
-            .forward_cookies(
-                incoming_cookies,
-                Url::parse("https://www.pickleballtournaments.com").unwrap(),
-            )
-            .default_header("Host", "www.pickleballtournaments.com")
-            .build();
-
-        Outcome::Success(client)
+        match request.rocket().state::<Client<'static>>() {
+            Some(client) => Outcome::Success(client.with_cookies(request.cookies())),
+            None => Outcome::Failure((Status::InternalServerError, ())),
+        }
     }
 }
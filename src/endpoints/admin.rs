@@ -0,0 +1,132 @@
+//! Cache introspection and invalidation.  These are only available when an `admin_token` is
+//! configured, and every request has to present it as a bearer token.
+
+use std::time::SystemTime;
+
+use chrono::prelude::*;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+use crate::client::{Client, Upstream};
+use crate::scrape::admin::ScrapeCacheReport;
+use crate::scrape::health::HealthReport;
+use crate::scrape::ScrapeCache;
+use crate::util::session_store::SessionStore;
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AdminConfig {
+    #[serde(default)]
+    admin_token: Option<String>,
+}
+
+/// Request guard for requests that carry the configured admin token
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let admin_token = match request.rocket().state::<AdminConfig>() {
+            Some(AdminConfig {
+                admin_token: Some(token),
+            }) => token,
+            // Pretend the admin endpoints don't exist if they haven't been set up.
+            _ => return Outcome::Failure((Status::NotFound, ())),
+        };
+
+        let bearer_token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer "));
+
+        match bearer_token {
+            Some(token) if constant_time_eq(token, admin_token) => Outcome::Success(Admin),
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[get("/admin/cache")]
+pub fn cache_report(_admin: Admin, cache: &State<ScrapeCache>) -> Json<ScrapeCacheReport> {
+    Json(cache.report())
+}
+
+#[delete("/admin/cache/tournament/<tournament_id>")]
+pub async fn invalidate_tournament(
+    _admin: Admin,
+    tournament_id: usize,
+    cache: &State<ScrapeCache>,
+    upstream: &State<Upstream>,
+) -> Status {
+    cache.invalidate_tournament(tournament_id, upstream).await;
+    Status::NoContent
+}
+
+#[delete("/admin/cache/tournament/<tournament_id>/event/<event_name>")]
+pub async fn invalidate_event(
+    _admin: Admin,
+    tournament_id: usize,
+    event_name: &str,
+    cache: &State<ScrapeCache>,
+) -> Status {
+    cache.invalidate_event(tournament_id, event_name).await;
+    Status::NoContent
+}
+
+#[delete("/admin/cache/page?<url>")]
+pub async fn invalidate_page(_admin: Admin, url: &str, cache: &State<ScrapeCache>) -> Status {
+    cache.invalidate_page(url).await;
+    Status::NoContent
+}
+
+/// Runs the scrapers' selectors and patterns against the pages of the given tournaments, or of the
+/// most recent past tournament if none are given.  With `fresh`, the pages are fetched again.
+#[get("/admin/health/selectors?<tournament>&<fresh>")]
+pub async fn selector_health(
+    _admin: Admin,
+    tournament: Vec<usize>,
+    fresh: bool,
+    client: Client<'_>,
+    cache: &State<ScrapeCache>,
+) -> Json<HealthReport> {
+    Json(cache.check_health(&tournament, fresh, &client).await)
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStatus {
+    /// When the upstream last accepted the server's session
+    last_valid: Option<String>,
+    /// When the upstream last asked the server's session to solve a captcha
+    last_captcha: Option<String>,
+    cookies: usize,
+    /// Whether the session is saved between runs
+    persistent: bool,
+}
+
+#[get("/admin/session")]
+pub fn session_status(_admin: Admin, session: &State<SessionStore>) -> Json<SessionStatus> {
+    let format_time = |time: SystemTime| DateTime::<Utc>::from(time).to_rfc3339();
+
+    Json(SessionStatus {
+        last_valid: session.last_valid().map(format_time),
+        last_captcha: session.last_captcha().map(format_time),
+        cookies: session.cookie_count(),
+        persistent: session.is_persistent(),
+    })
+}
+
+/// Compares without bailing out at the first difference, so the token can't be guessed by timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
@@ -10,11 +10,11 @@ use crate::scrape::event::{event_bracket, event_team_list, Bracket, TeamList};
 use crate::scrape::tournament_event_group_list::{tournament_event_group_list, EventUrl};
 use crate::scrape::tournament_list::{tournament_list, TournamentListing};
 use crate::scrape::tournament_schedule::{tournament_schedule, ScheduleItem};
-use crate::scrape::{ScrapeCache, ScrapeError, ScrapeResult};
+use crate::scrape::{ParseWarning, ScrapeCache, ScrapeError, ScrapeResult};
 
 #[get("/tournament/<id>/event/<event>")]
 pub fn page(id: usize, event: &str) -> Template {
-    let context = std::array::IntoIter::new([
+    let context = IntoIterator::into_iter([
         ("id".to_owned(), id.to_string()),
         ("event".to_owned(), event.to_owned()),
     ])
@@ -38,6 +38,10 @@ pub struct EventData {
     teams: TeamList,
     bracket: Option<Bracket>,
     tournament: TournamentListing,
+    /// Whether any of the above is older than it should be because pickleballtournaments.com couldn't be reached
+    stale: bool,
+    /// What was left out of the above because it couldn't be parsed
+    warnings: Vec<ParseWarning>,
 }
 
 #[get("/tournament/<id>/event/<event_name>/data")]
@@ -47,32 +51,45 @@ pub async fn data(
     client: Client<'_>,
     cache: &State<ScrapeCache>,
 ) -> ScrapeResult<Json<EventDataPayload>> {
-    let listing = tournament_list(&client, cache)
-        .await?
-        .iter()
-        .find(|t| t.id == id)
-        .cloned()
-        .ok_or_else(|| ScrapeError::from_str("tournament not found"))?;
+    let (listing, listing_stale) = {
+        let tournament_list = tournament_list(&client, cache).await?;
+        let listing = tournament_list.iter().find(|t| t.id == id).cloned();
+        (listing, tournament_list.is_stale())
+    };
+    let listing = listing.ok_or_else(|| ScrapeError::from_str("tournament not found"))?;
 
-    let event = tournament_event_group_list(id, &client, cache)
-        .await?
-        .iter()
-        .flat_map(|g| g.events.iter())
-        .find(|e| e.name == event_name)
-        .cloned()
-        .ok_or_else(|| ScrapeError::from_str("event not found"))?;
+    let (event, event_groups_stale) = {
+        let event_groups = tournament_event_group_list(id, &client, cache).await?;
+        let event = event_groups
+            .iter()
+            .flat_map(|g| g.events.iter())
+            .find(|e| e.name == event_name)
+            .cloned();
+        (event, event_groups.is_stale())
+    };
+    let event = event.ok_or_else(|| ScrapeError::from_str("event not found"))?;
 
-    let schedule_item = tournament_schedule(id, &client, cache)
-        .await?
-        .iter()
-        .find(|e| e.event == event.name)
-        .cloned();
+    let (schedule_item, schedule_stale) = {
+        let schedule = tournament_schedule(id, &client, cache).await?;
+        let schedule_item = schedule.iter().find(|e| e.event == event.name).cloned();
+        (schedule_item, schedule.is_stale())
+    };
 
-    let teams = event_team_list(id, &event, &client, cache).await?.clone();
+    let mut warnings = Vec::new();
+
+    let (teams, teams_stale) = {
+        let teams = event_team_list(id, &event, &client, cache).await?;
+        warnings.extend_from_slice(teams.warnings());
+        (TeamList::clone(&teams), teams.is_stale())
+    };
 
-    let bracket = match event.url {
-        EventUrl::Bracket(_) => Some(event_bracket(id, &event, &client, cache).await?.clone()),
-        _ => None,
+    let (bracket, bracket_stale) = match event.url {
+        EventUrl::Bracket(_) => {
+            let bracket = event_bracket(id, &event, &client, cache).await?;
+            warnings.extend_from_slice(bracket.warnings());
+            (Some(Bracket::clone(&bracket)), bracket.is_stale())
+        }
+        _ => (None, false),
     };
 
     Ok(Json(EventDataPayload::EventData(EventData {
@@ -81,5 +98,11 @@ pub async fn data(
         teams,
         bracket,
         tournament: listing,
+        stale: listing_stale
+            || event_groups_stale
+            || schedule_stale
+            || teams_stale
+            || bracket_stale,
+        warnings,
     })))
 }
@@ -0,0 +1,14 @@
+//! Exposes the metrics for Prometheus to scrape
+
+use rocket::http::ContentType;
+use rocket::State;
+
+use crate::scrape::ScrapeCache;
+use crate::util::metrics::METRICS;
+
+#[get("/metrics")]
+pub fn metrics(cache: &State<ScrapeCache>) -> (ContentType, String) {
+    let content_type = ContentType::with_params("text", "plain", ("version", "0.0.4"));
+
+    (content_type, METRICS.render(cache.stats()))
+}
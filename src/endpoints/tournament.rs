@@ -11,12 +11,12 @@ use crate::scrape::tournament_info::{tournament_info, Info};
 use crate::scrape::tournament_list::{tournament_list, TournamentListing};
 use crate::scrape::tournament_player_list::{tournament_player_list, PlayerList};
 use crate::scrape::tournament_schedule::{tournament_schedule, Schedule};
-use crate::scrape::{ScrapeCache, ScrapeError, ScrapeResult};
+use crate::scrape::{ParseWarning, ScrapeCache, ScrapeError, ScrapeResult};
 
 #[get("/tournament/<id>")]
 pub fn page(id: usize) -> Template {
     let context =
-        std::array::IntoIter::new([("id".to_owned(), id.to_string())]).collect::<HashMap<_, _>>();
+        IntoIterator::into_iter([("id".to_owned(), id.to_string())]).collect::<HashMap<_, _>>();
     Template::render("tournament", &context)
 }
 
@@ -36,6 +36,10 @@ pub struct TournamentData {
     event_groups: EventGroupList,
     schedule: Schedule,
     info: Info,
+    /// Whether any of the above is older than it should be because pickleballtournaments.com couldn't be reached
+    stale: bool,
+    /// What was left out of the above because it couldn't be parsed
+    warnings: Vec<ParseWarning>,
 }
 
 #[get("/tournament/<id>/data")]
@@ -44,12 +48,12 @@ pub async fn data(
     client: Client<'_>,
     cache: &State<ScrapeCache>,
 ) -> ScrapeResult<Json<TournamentDataPayload>> {
-    let listing = tournament_list(&client, cache)
-        .await?
-        .iter()
-        .find(|t| t.id == id)
-        .cloned()
-        .ok_or_else(|| ScrapeError::from_str("tournament not found"))?;
+    let (listing, listing_stale) = {
+        let tournament_list = tournament_list(&client, cache).await?;
+        let listing = tournament_list.iter().find(|t| t.id == id).cloned();
+        (listing, tournament_list.is_stale())
+    };
+    let listing = listing.ok_or_else(|| ScrapeError::from_str("tournament not found"))?;
 
     let player_list = tournament_player_list(id, &client, cache).await?;
     let event_group_list = tournament_event_group_list(id, &client, cache).await?;
@@ -59,10 +63,23 @@ pub async fn data(
     Ok(Json(TournamentDataPayload::TournamentData(
         TournamentData {
             listing,
-            players: player_list.clone(),
-            event_groups: event_group_list.clone(),
-            schedule: schedule.clone(),
-            info: info.clone(),
+            players: PlayerList::clone(&player_list),
+            event_groups: EventGroupList::clone(&event_group_list),
+            schedule: Schedule::clone(&schedule),
+            info: Info::clone(&info),
+            stale: listing_stale
+                || player_list.is_stale()
+                || event_group_list.is_stale()
+                || schedule.is_stale()
+                || info.is_stale(),
+            warnings: player_list
+                .warnings()
+                .iter()
+                .chain(event_group_list.warnings())
+                .chain(schedule.warnings())
+                .chain(info.warnings())
+                .cloned()
+                .collect(),
         },
     )))
 }
@@ -9,7 +9,7 @@ use rocket_dyn_templates::Template;
 
 use crate::client::Client;
 use crate::scrape::tournament_list::{tournament_list, TournamentList};
-use crate::scrape::{ScrapeCache, ScrapeResult};
+use crate::scrape::{ParseWarning, ScrapeCache, ScrapeResult};
 
 #[get("/tournaments")]
 pub fn search() -> Template {
@@ -21,7 +21,16 @@ pub fn search() -> Template {
 #[serde(crate = "rocket::serde")]
 #[serde(rename_all = "camelCase")]
 pub enum TournamentListPayload {
-    Tournaments(TournamentList),
+    TournamentList(TournamentListData),
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct TournamentListData {
+    tournaments: TournamentList,
+    /// What was left out of the tournaments because it couldn't be parsed
+    warnings: Vec<ParseWarning>,
 }
 
 #[get("/tournaments/fetch")]
@@ -31,7 +40,10 @@ pub async fn data(
 ) -> ScrapeResult<Json<TournamentListPayload>> {
     let tournament_list = tournament_list(&client, cache).await?;
 
-    Ok(Json(TournamentListPayload::Tournaments(
-        tournament_list.clone(),
+    Ok(Json(TournamentListPayload::TournamentList(
+        TournamentListData {
+            tournaments: TournamentList::clone(&tournament_list),
+            warnings: tournament_list.warnings().to_vec(),
+        },
     )))
 }
@@ -2,12 +2,21 @@
 extern crate rocket;
 
 use std::collections::HashMap;
+use std::path::Path;
 
+use rocket::fairing::AdHoc;
 use rocket::fs::{relative, FileServer};
+use rocket::{Build, Ignite, Rocket};
 use rocket_dyn_templates::Template;
 
-use self::endpoints::{event, tournament, tournaments};
-use self::scrape::ScrapeCache;
+use self::client::{Client, Upstream};
+use self::endpoints::admin::{self, AdminConfig};
+use self::endpoints::{event, metrics, tournament, tournaments};
+use self::scrape::{warming, ScrapeCache};
+use self::util::logging;
+use self::util::metrics::EndpointTimer;
+use self::util::rate_limit::RateLimiter;
+use self::util::session_store::SessionStore;
 
 mod client;
 mod endpoints;
@@ -31,15 +40,142 @@ fn not_found_page() -> Template {
     not_found()
 }
 
-#[launch]
-fn rocket() -> _ {
+#[rocket::main]
+async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    match args.first().map(String::as_str) {
+        Some("check-selectors") => check_selectors(&args[1..]).await,
+        Some("save-fixtures") => save_fixtures(&args[1..]).await,
+        Some(command) => {
+            eprintln!("unknown command {:?}", command);
+            eprintln!(
+                "usage: pickleballtournamentsportal [check-selectors [--fresh] [TOURNAMENT_ID...] \
+                 | save-fixtures TOURNAMENT_ID...]"
+            );
+            std::process::exit(2);
+        }
+        None => {
+            let _result = rocket().launch().await;
+        }
+    }
+}
+
+/// Runs the selector health check against the configured upstream and prints the report, exiting
+/// with an error if anything looks broken.  Pages come from the page store unless `--fresh` is given.
+async fn check_selectors(args: &[String]) {
+    let fresh = args.iter().any(|a| a == "--fresh");
+    let tournament_ids = parse_tournament_ids(args.iter().filter(|a| *a != "--fresh"));
+
+    let rocket = ignite().await;
+    let (client, cache) = client_and_cache(&rocket);
+
+    let report = cache.check_health(&tournament_ids, fresh, client).await;
+    println!("{}", report);
+
+    if !report.is_healthy() {
+        std::process::exit(1);
+    }
+}
+
+/// Replaces the pages the parser snapshot tests read with the real ones from the configured
+/// upstream.  The snapshots then need to be updated, by running the tests with `UPDATE_SNAPSHOTS=1`.
+async fn save_fixtures(args: &[String]) {
+    let tournament_ids = parse_tournament_ids(args.iter());
+    if tournament_ids.is_empty() {
+        eprintln!("usage: pickleballtournamentsportal save-fixtures TOURNAMENT_ID...");
+        std::process::exit(2);
+    }
+
+    let rocket = ignite().await;
+    let (client, cache) = client_and_cache(&rocket);
+
+    let dir = Path::new(relative!("fixtures/pages"));
+    match cache.capture_fixtures(&tournament_ids, dir, client).await {
+        Ok(missing) if missing.is_empty() => println!("saved every page to {:?}", dir),
+        Ok(missing) => {
+            eprintln!(
+                "saved pages to {:?}, but none of the tournaments had: {}",
+                dir,
+                missing.join(", ")
+            );
+            std::process::exit(1);
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_tournament_ids<'a>(args: impl Iterator<Item = &'a String>) -> Vec<usize> {
+    match args
+        .map(|a| a.parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(tournament_ids) => tournament_ids,
+        Err(error) => {
+            eprintln!("invalid tournament id: {}", error);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Igniting runs the fairings that set up the client and caches, without serving anything.
+async fn ignite() -> Rocket<Ignite> {
+    match rocket().ignite().await {
+        Ok(rocket) => rocket,
+        Err(error) => {
+            // Displaying the error marks it handled, so that dropping it doesn't panic
+            eprintln!("could not start: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn client_and_cache(rocket: &Rocket<Ignite>) -> (&Client<'static>, &ScrapeCache) {
+    match (
+        rocket.state::<Client<'static>>(),
+        rocket.state::<ScrapeCache>(),
+    ) {
+        (Some(client), Some(cache)) => (client, cache),
+        _ => {
+            eprintln!("the client and scrape cache weren't set up");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn rocket() -> Rocket<Build> {
+    logging::init();
+
     rocket::build()
         .mount("/", routes![landing_page, not_found_page])
         .mount("/", routes![tournaments::data, tournaments::search])
         .mount("/", routes![tournament::data, tournament::page])
         .mount("/", routes![event::data, event::page])
+        .mount("/", routes![metrics::metrics])
+        .mount(
+            "/",
+            routes![
+                admin::cache_report,
+                admin::invalidate_tournament,
+                admin::invalidate_event,
+                admin::invalidate_page,
+                admin::session_status,
+                admin::selector_health,
+            ],
+        )
         .mount("/", FileServer::from(relative!("static")))
         .register("/", catchers![not_found])
         .attach(Template::fairing())
-        .manage(ScrapeCache::default())
+        .attach(EndpointTimer)
+        .attach(AdHoc::config::<AdminConfig>())
+        .attach(Upstream::fairing())
+        .attach(RateLimiter::fairing())
+        .attach(SessionStore::fairing())
+        .attach(Client::fairing())
+        .attach(ScrapeCache::fairing())
+        .attach(ScrapeCache::eviction_fairing())
+        .attach(warming::fairing())
 }
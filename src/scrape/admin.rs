@@ -0,0 +1,205 @@
+//! Lets an administrator see what's in the scrape caches and throw out entries that have gone bad,
+//! e.g. when an organizer fixes a bracket on pickleballtournaments.com.
+
+use reqwest::Url;
+use rocket::serde::Serialize;
+
+use crate::client::Upstream;
+use crate::scrape::ScrapeCache;
+use crate::util::cache::{Cache, CacheMap, CacheStats};
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct ScrapeCacheReport {
+    event_bracket: CacheReport<EventKey>,
+    event_team_list: CacheReport<EventKey>,
+    pages: CacheReport<PageKey>,
+    tournament_list: CacheReport<()>,
+    tournament_event_list: CacheReport<TournamentKey>,
+    tournament_info: CacheReport<TournamentKey>,
+    tournament_player_list: CacheReport<TournamentKey>,
+    tournament_schedule: CacheReport<TournamentKey>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct CacheReport<K> {
+    hits: usize,
+    misses: usize,
+    entries: Vec<EntryReport<K>>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct EntryReport<K> {
+    #[serde(flatten)]
+    key: K,
+    /// Seconds since the entry was last updated, or nothing if it never has been
+    age: Option<u64>,
+    /// Approximate size in bytes
+    size: usize,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TournamentKey {
+    tournament: usize,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct EventKey {
+    tournament: usize,
+    event: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PageKey {
+    url: String,
+}
+
+impl ScrapeCache {
+    pub fn report(&self) -> ScrapeCacheReport {
+        let event_key = |(tournament, event): (usize, String)| EventKey { tournament, event };
+        let tournament_key = |tournament| TournamentKey { tournament };
+
+        ScrapeCacheReport {
+            event_bracket: map_report(&self.event_bracket, event_key),
+            event_team_list: map_report(&self.event_team_list, event_key),
+            pages: report(
+                self.pages.stats(),
+                self.pages.entries().iter().map(|(url, c)| {
+                    entry_report(
+                        PageKey {
+                            url: url.to_string(),
+                        },
+                        c,
+                    )
+                }),
+            ),
+            tournament_list: report(
+                self.tournament_list.stats(),
+                std::iter::once(entry_report((), &self.tournament_list)),
+            ),
+            tournament_event_list: map_report(&self.tournament_event_list, tournament_key),
+            tournament_info: map_report(&self.tournament_info, tournament_key),
+            tournament_player_list: map_report(&self.tournament_player_list, tournament_key),
+            tournament_schedule: map_report(&self.tournament_schedule, tournament_key),
+        }
+    }
+
+    /// The hit, miss and refresh counts of each cache, by name
+    pub fn stats(&self) -> Vec<(&'static str, &CacheStats)> {
+        vec![
+            ("event_bracket", self.event_bracket.stats()),
+            ("event_team_list", self.event_team_list.stats()),
+            ("pages", self.pages.stats()),
+            ("tournament_list", self.tournament_list.stats()),
+            ("tournament_event_list", self.tournament_event_list.stats()),
+            ("tournament_info", self.tournament_info.stats()),
+            (
+                "tournament_player_list",
+                self.tournament_player_list.stats(),
+            ),
+            ("tournament_schedule", self.tournament_schedule.stats()),
+        ]
+    }
+
+    /// Drops everything cached for a tournament, including its pages and all of its events
+    pub async fn invalidate_tournament(&self, tournament_id: usize, upstream: &Upstream) {
+        let mut page_urls = vec![
+            upstream.url(&format!("tournamentinfo.pl?tid={}", tournament_id)),
+            upstream.url(&format!("cinfo.pl?tid={}", tournament_id)),
+        ];
+
+        // The event pages can only be found through the event list, so it has to go last.
+        if let Some(event_list_cache) = self.tournament_event_list.get_existing(&tournament_id) {
+            if let Some(event_groups) = event_list_cache.peek() {
+                page_urls.extend(
+                    event_groups
+                        .iter()
+                        .flat_map(|g| g.events.iter())
+                        .map(|e| e.url.as_str().to_owned()),
+                );
+            }
+        }
+
+        for url in page_urls {
+            self.invalidate_page(&url).await;
+        }
+
+        self.event_bracket
+            .remove_where(|(t, _)| *t == tournament_id);
+        self.event_team_list
+            .remove_where(|(t, _)| *t == tournament_id);
+        self.tournament_info.remove_where(|t| *t == tournament_id);
+        self.tournament_player_list
+            .remove_where(|t| *t == tournament_id);
+        self.tournament_schedule
+            .remove_where(|t| *t == tournament_id);
+        self.tournament_event_list
+            .remove_where(|t| *t == tournament_id);
+    }
+
+    /// Drops the cached bracket and team list of an event, along with its page
+    pub async fn invalidate_event(&self, tournament_id: usize, event_name: &str) {
+        if let Some(event_list_cache) = self.tournament_event_list.get_existing(&tournament_id) {
+            let event_url = match event_list_cache.peek() {
+                Some(event_groups) => event_groups
+                    .iter()
+                    .flat_map(|g| g.events.iter())
+                    .find(|e| e.name == event_name)
+                    .map(|e| e.url.as_str().to_owned()),
+                None => None,
+            };
+
+            if let Some(url) = event_url {
+                self.invalidate_page(&url).await;
+            }
+        }
+
+        let is_event = |(t, e): &(usize, String)| *t == tournament_id && e == event_name;
+        self.event_bracket.remove_where(is_event);
+        self.event_team_list.remove_where(is_event);
+    }
+
+    /// Drops a single page.  Anything that was parsed from it is parsed again when next used.
+    pub async fn invalidate_page(&self, url: &str) {
+        if let Ok(url) = Url::parse(url) {
+            self.pages.remove(&url).await;
+        }
+    }
+}
+
+fn map_report<K, V, R>(map: &CacheMap<K, V>, key: impl Fn(K) -> R) -> CacheReport<R>
+where
+    K: Clone + Eq + std::hash::Hash,
+    V: Default + Serialize,
+{
+    report(
+        map.stats(),
+        map.entries()
+            .into_iter()
+            .map(|(k, c)| entry_report(key(k), &c)),
+    )
+}
+
+fn report<K>(stats: &CacheStats, entries: impl Iterator<Item = EntryReport<K>>) -> CacheReport<K> {
+    CacheReport {
+        hits: stats.hits(),
+        misses: stats.misses(),
+        entries: entries.collect(),
+    }
+}
+
+fn entry_report<K, V: Default + Serialize>(key: K, cache: &Cache<V>) -> EntryReport<K> {
+    EntryReport {
+        key,
+        age: cache.age().map(|a| a.as_secs()),
+        size: cache.size(),
+    }
+}
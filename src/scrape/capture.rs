@@ -0,0 +1,152 @@
+//! Saves real pages from pickleballtournaments.com to be used as the fixtures of the parser
+//! snapshot tests, so that the tests run against the markup the scrapers actually see.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use async_std::fs;
+use reqwest::Url;
+use scraper::Html;
+
+use crate::client::{Client, RequestBuilder, RequestClass};
+use crate::scrape::event::is_round_robin;
+use crate::scrape::tournament_event_group_list::{
+    parse_event_bracket_list, parse_event_list_urls, EventUrl,
+};
+use crate::scrape::{ParseWarnings, ScrapeCache, ScrapeError, ScrapeResult};
+use crate::util::cache::{CacheSnapshot, Page};
+
+/// The pages the snapshot tests read, by the names they're saved under
+pub const FIXTURE_PAGES: [&str; 7] = [
+    "tournament_list",
+    "tournament_page",
+    "event_bracket_list",
+    "ereport",
+    "rptbrackets",
+    "bracket_round_robin",
+    "bracket_double_elim",
+];
+
+impl ScrapeCache {
+    /// Fetches the pages the snapshot tests read, and saves them to `dir`.  The tournament and
+    /// Events/Brackets pages come from the first of `tournament_ids`, and each kind of event page
+    /// from the first of them that has one.  Any bracket that isn't a round robin is saved as the
+    /// elimination bracket.  Returns the names of the pages that none of the tournaments had.
+    pub async fn capture_fixtures(
+        &self,
+        tournament_ids: &[usize],
+        dir: &Path,
+        client: &Client<'_>,
+    ) -> ScrapeResult<Vec<&'static str>> {
+        let upstream = client.upstream();
+        let mut pages = BTreeMap::new();
+
+        let tournament_list = self
+            .load_fresh(&upstream.url("pbt_tlisting.pl?when=F"), |url| {
+                client.get(url).class(RequestClass::Listing)
+            })
+            .await?;
+        pages.insert("tournament_list", tournament_list.body.clone());
+
+        for &tournament_id in tournament_ids {
+            let tournament_page_url =
+                upstream.url(&format!("tournamentinfo.pl?tid={}", tournament_id));
+            let event_bracket_page_url = upstream.url(&format!("cinfo.pl?tid={}", tournament_id));
+            let from_tournament_page = |url| {
+                client
+                    .get(url)
+                    .header("Referer", &tournament_page_url)
+                    .header("Sec-Fetch-Site", "same-origin")
+            };
+
+            let tournament_page = self
+                .load_fresh(&tournament_page_url, |url| client.get(url))
+                .await?;
+            let event_bracket_page = self
+                .load_fresh(&event_bracket_page_url, from_tournament_page)
+                .await?;
+            pages
+                .entry("tournament_page")
+                .or_insert_with(|| tournament_page.body.clone());
+            pages
+                .entry("event_bracket_list")
+                .or_insert_with(|| event_bracket_page.body.clone());
+
+            // Whatever the parsers can't read is of no concern here.
+            let event_list_urls = parse_event_list_urls(
+                &tournament_page.body,
+                upstream,
+                &ParseWarnings::new(&tournament_page_url),
+            );
+            let event_urls = parse_event_bracket_list(
+                &event_bracket_page.body,
+                upstream,
+                &ParseWarnings::new(&event_bracket_page_url),
+            )
+            .into_iter()
+            .flat_map(|g| g.events)
+            .map(|e| e.url)
+            .collect::<Vec<_>>();
+
+            if let (false, Some((_, url))) =
+                (pages.contains_key("ereport"), event_list_urls.first())
+            {
+                let page = self.load_fresh(url, from_tournament_page).await?;
+                pages.insert("ereport", page.body.clone());
+            }
+
+            for event_url in event_urls {
+                match event_url {
+                    EventUrl::List(url) if !pages.contains_key("rptbrackets") => {
+                        let page = self.load_fresh(&url, |url| client.get(url)).await?;
+                        pages.insert("rptbrackets", page.body.clone());
+                    }
+                    EventUrl::Bracket(url)
+                        if !pages.contains_key("bracket_round_robin")
+                            || !pages.contains_key("bracket_double_elim") =>
+                    {
+                        let page = self
+                            .load_fresh(&url, |url| client.get(url).class(RequestClass::Bracket))
+                            .await?;
+                        let name = if is_round_robin(&Html::parse_document(&page.body)) {
+                            "bracket_round_robin"
+                        } else {
+                            "bracket_double_elim"
+                        };
+                        pages.entry(name).or_insert_with(|| page.body.clone());
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        for (name, body) in pages.iter() {
+            let path = dir.join(name).with_extension("html");
+            fs::write(&path, body).await.map_err(|e| {
+                ScrapeError::from_str(&format!("could not write {:?}: {}", path, e))
+            })?;
+        }
+
+        Ok(FIXTURE_PAGES
+            .iter()
+            .copied()
+            .filter(|n| !pages.contains_key(n))
+            .collect())
+    }
+
+    async fn load_fresh<'c>(
+        &self,
+        url: &str,
+        request: impl Fn(Url) -> RequestBuilder<'c>,
+    ) -> ScrapeResult<CacheSnapshot<Page>> {
+        self.pages
+            .retrieve_or_update(
+                Duration::ZERO,
+                url,
+                request,
+                "could not load page for fixtures",
+            )
+            .await
+    }
+}
@@ -1,23 +1,21 @@
 use std::fmt;
 use std::ops::{Deref, Neg};
-use std::time::Duration;
 
 use once_cell::sync::Lazy;
 use regex::Regex;
 use rocket::serde::Serialize;
 use scraper::{ElementRef, Html, Selector};
+use tracing::field::Empty;
+use tracing::instrument;
 
-use crate::client::Client;
+use crate::client::{Client, RequestClass};
+use crate::scrape::health::Check;
 use crate::scrape::tournament_event_group_list::{Event, EventUrl};
 use crate::scrape::tournament_player_list::{
     find_player, tournament_player_list, FindPlayerQuery, Player, PlayerList,
 };
-use crate::scrape::{
-    ScrapeCache, ScrapeError, ScrapeResult, EVENT_BRACKET_REFRESH, EVENT_TEAM_LIST_REFRESH,
-    TOURNAMENT_EVENT_PLAYER_LIST_PAGES_REFRESH,
-};
-use crate::util::cache::{CacheGuard, CacheMapGuard};
-use crate::util::guard_stack::GuardStack;
+use crate::scrape::{ParseWarningKind, ParseWarnings, ScrapeCache, ScrapeError, ScrapeResult};
+use crate::util::cache::{Cache, CacheSnapshot};
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(crate = "rocket::serde")]
@@ -27,246 +25,231 @@ pub enum Bracket {
     RoundRobin(Vec<Vec<BracketMatch>>),
 }
 
+impl Bracket {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Bracket::DoubleElim(brackets) => brackets.is_empty(),
+            Bracket::RoundRobin(rounds) => rounds.iter().all(Vec::is_empty),
+        }
+    }
+
+    /// Whether any match in the bracket has been won
+    pub fn has_winners(&self) -> bool {
+        match self {
+            Bracket::DoubleElim(brackets) => brackets.iter().any(|(_, m)| m.has_winners()),
+            Bracket::RoundRobin(rounds) => rounds.iter().flatten().any(BracketMatch::has_winners),
+        }
+    }
+}
+
 impl Default for Bracket {
     fn default() -> Self {
         Self::RoundRobin(Vec::new())
     }
 }
 
-pub type EventBracketGuard<'a> = GuardStack<
-    'a,
-    (
-        CacheMapGuard<'a, (usize, String), Bracket>,
-        CacheGuard<'a, Bracket>,
-    ),
-    Bracket,
->;
-
+#[instrument(skip(event, client, cache), err, fields(event = %event.name, cache = Empty, parse_ms = Empty, unchanged = Empty))]
 pub async fn event_bracket<'a>(
     tournament_id: usize,
     event: &Event,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
-) -> ScrapeResult<EventBracketGuard<'a>> {
-    cache
-        .event_bracket
-        .get((tournament_id, event.name.clone()))
-        .await
-        .try_push_guard_async(|event_bracket_cache| async move {
-            event_bracket_cache
-                .retrieve_or_update(Duration::from_secs(EVENT_BRACKET_REFRESH), || async {
-                    let teams = event_team_list(tournament_id, event, client, cache).await?;
-
-                    let page_raw_html = cache
-                        .pages
-                        .retrieve_or_update(
-                            Duration::from_secs(EVENT_BRACKET_REFRESH),
-                            event.url.as_str(),
-                            |url| async { client.get(url).send().await },
-                            "could not load event bracket",
-                        )
-                        .await?
-                        .clone();
-
-                    let page = Html::parse_document(&page_raw_html);
-
-                    if is_round_robin(&page) {
-                        Ok(Bracket::RoundRobin(
-                            page.select(&SELECTORS.bracket_table)
-                                .next()
-                                .and_then(|t| t.select(&SELECTORS.row).nth(4))
-                                .map(|r| {
-                                    r.select(&SELECTORS.cell)
-                                        .skip(1)
-                                        .step_by(3)
-                                        .map(|c| {
-                                            std::iter::successors(Some(GridCell(c)), |c| {
-                                                c.neighbor(Direction::Down)
-                                            })
-                                            .step_by(4)
-                                            .map(BracketPosition)
-                                            .map(BracketNode::crawl_from)
-                                            .map(|n| BracketMatch::from_node(&n, &teams))
-                                            .collect()
-                                        })
-                                        .collect()
-                                })
-                                .unwrap_or_default(),
-                        ))
-                    } else {
-                        Ok(Bracket::DoubleElim(
-                            page.select(&SELECTORS.bracket_table)
-                                .filter_map(|t| {
-                                    t.select(&SELECTORS.match_label).max_by_key(|l| {
-                                        l.inner_html()[1..].parse::<usize>().unwrap()
-                                    })
-                                })
-                                .filter_map(|l| {
-                                    l.ancestors().nth(1).and_then(|l| l.next_siblings().nth(1))
-                                })
-                                .filter_map(ElementRef::wrap)
-                                .map(GridCell)
-                                .map(BracketPosition)
-                                .map(BracketNode::crawl_from)
-                                .map(|n| BracketMatch::from_node(&n, &teams))
-                                .zip(
-                                    page.select(&SELECTORS.bracket_name)
-                                        .map(|e| Some(e.inner_html()))
-                                        .chain(std::iter::repeat(None)),
-                                )
-                                .map(|(b, n)| (n, b))
-                                .collect(),
-                        ))
-                    }
-                })
-                .await
-        })
+) -> ScrapeResult<CacheSnapshot<Bracket>> {
+    // Brackets change quickly during a tournament, so rather than make visitors wait on
+    // pickleballtournaments.com, show them the last bracket while it's refreshed in the background.
+    let background_client = client.detached();
+    let background_cache = cache.clone();
+    let background_event = event.clone();
+
+    let event_bracket_cache = cache.event_bracket.get((tournament_id, event.name.clone()));
+
+    let background_bracket_cache = Cache::clone(&event_bracket_cache);
+
+    event_bracket_cache
+        .retrieve_or_revalidate(
+            cache.refresh_interval(tournament_id, cache.refresh.event_bracket),
+            move || {
+                let client = background_client.clone();
+                let cache = background_cache.clone();
+                let event = background_event.clone();
+                let bracket_cache = background_bracket_cache.clone();
+                async move {
+                    scrape_event_bracket(tournament_id, &event, &bracket_cache, &client, &cache)
+                        .await
+                }
+            },
+        )
         .await
 }
 
-pub type TeamList = Vec<PlayerList>;
+async fn scrape_event_bracket(
+    tournament_id: usize,
+    event: &Event,
+    bracket_cache: &Cache<Bracket>,
+    client: &Client<'_>,
+    cache: &ScrapeCache,
+) -> ScrapeResult<Bracket> {
+    let teams = event_team_list(tournament_id, event, client, cache).await?;
 
-pub type EventTeamListGuard<'a> = GuardStack<
-    'a,
-    (
-        CacheMapGuard<'a, (usize, String), TeamList>,
-        CacheGuard<'a, TeamList>,
-    ),
-    TeamList,
->;
+    let fetched_page = cache
+        .pages
+        .retrieve_or_update(
+            cache.refresh_interval(tournament_id, cache.refresh.event_bracket),
+            event.url.as_str(),
+            |url| client.get(url).class(RequestClass::Bracket),
+            "could not load event bracket",
+        )
+        .await?;
 
+    bracket_cache.depend_on(&teams);
+    bracket_cache.depend_on(&fetched_page);
+    bracket_cache.derive((&*teams, fetched_page.hash), || {
+        Ok(parse_bracket(&fetched_page.body, &teams))
+    })
+}
+
+pub type TeamList = Vec<PlayerList>;
+
+#[instrument(skip(event, client, cache), err, fields(event = %event.name, cache = Empty, parse_ms = Empty, unchanged = Empty))]
 pub async fn event_team_list<'a>(
     tournament_id: usize,
     event: &Event,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
-) -> ScrapeResult<EventTeamListGuard<'a>> {
-    cache
+) -> ScrapeResult<CacheSnapshot<TeamList>> {
+    let event_team_list_cache = cache
         .event_team_list
-        .get((tournament_id, event.name.clone()))
-        .await
-        .try_push_guard_async(|event_team_list_cache| async move {
-            event_team_list_cache
-                .retrieve_or_update(Duration::from_secs(EVENT_TEAM_LIST_REFRESH), || async {
-                    let tournament_page_url = format!(
-                        "https://www.pickleballtournaments.com/tournamentinfo.pl?tid={}",
-                        tournament_id
-                    );
-
-                    let tournament_player_list =
-                        tournament_player_list(tournament_id, client, cache).await?;
-
-                    let team_list = match event.url {
-                        EventUrl::List(_) => {
-                            scrape_team_list_rptbrackets(
-                                event,
-                                &*tournament_player_list,
-                                client,
-                                cache,
-                            )
-                            .await?
-                        }
-                        EventUrl::GroupList(_) => {
-                            scrape_team_list_ereport(
-                                event,
-                                &tournament_page_url,
-                                &*tournament_player_list,
-                                client,
-                                cache,
-                            )
-                            .await?
-                        }
-                        EventUrl::Bracket(_) => {
-                            scrape_team_list_bracket(event, &*tournament_player_list, client, cache)
-                                .await?
-                        }
-                    };
+        .get((tournament_id, event.name.clone()));
+
+    event_team_list_cache
+        .retrieve_or_serve_stale(
+            cache.refresh_interval(tournament_id, cache.refresh.event_team_list),
+            || async {
+                let tournament_page_url = client
+                    .upstream()
+                    .url(&format!("tournamentinfo.pl?tid={}", tournament_id));
+
+                let tournament_player_list =
+                    tournament_player_list(tournament_id, client, cache).await?;
+                event_team_list_cache.depend_on(&tournament_player_list);
+
+                let team_list = match event.url {
+                    EventUrl::List(_) => {
+                        scrape_team_list_rptbrackets(
+                            tournament_id,
+                            event,
+                            &tournament_player_list,
+                            &event_team_list_cache,
+                            client,
+                            cache,
+                        )
+                        .await?
+                    }
+                    EventUrl::GroupList(_) => {
+                        scrape_team_list_ereport(
+                            tournament_id,
+                            event,
+                            &tournament_page_url,
+                            &tournament_player_list,
+                            &event_team_list_cache,
+                            client,
+                            cache,
+                        )
+                        .await?
+                    }
+                    EventUrl::Bracket(_) => {
+                        scrape_team_list_bracket(
+                            tournament_id,
+                            event,
+                            &tournament_player_list,
+                            &event_team_list_cache,
+                            client,
+                            cache,
+                        )
+                        .await?
+                    }
+                };
 
-                    Ok(team_list)
-                })
-                .await
-        })
+                Ok(team_list)
+            },
+        )
         .await
 }
 
 async fn scrape_team_list_rptbrackets<'a>(
+    tournament_id: usize,
     event: &Event,
     tournament_player_list: &[Player],
+    team_list_cache: &Cache<TeamList>,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
 ) -> ScrapeResult<TeamList> {
-    let page_raw_html = cache
+    let fetched_page = cache
         .pages
         .retrieve_or_update(
-            Duration::from_secs(TOURNAMENT_EVENT_PLAYER_LIST_PAGES_REFRESH),
+            cache.refresh_interval(
+                tournament_id,
+                cache.refresh.tournament_event_player_list_pages,
+            ),
             event.url.as_str(),
-            |url| async { client.get(url).send().await },
+            |url| client.get(url),
             "could not load event player list",
         )
-        .await?
-        .clone();
-
-    let page = Html::parse_document(&page_raw_html);
-
-    Ok(page
-        .select(&SELECTORS.rpt_player)
-        .map(|e| e.inner_html())
-        .map(|t| {
-            t.split('/')
-                .filter(|s| !s.trim().is_empty())
-                .filter_map(|s| name_to_player(s, tournament_player_list, &page_raw_html))
-                .collect::<Vec<_>>()
-        })
-        .collect())
+        .await?;
+
+    team_list_cache.depend_on(&fetched_page);
+    team_list_cache.derive((tournament_player_list, fetched_page.hash), || {
+        let warnings = ParseWarnings::new(event.url.as_str());
+        let team_list =
+            parse_team_list_rptbrackets(&fetched_page.body, tournament_player_list, &warnings);
+        team_list_cache.warn(warnings.into_vec());
+        Ok(team_list)
+    })
 }
 
 async fn scrape_team_list_ereport<'a>(
+    tournament_id: usize,
     event: &Event,
     tournament_page_url: &str,
     tournament_player_list: &[Player],
+    team_list_cache: &Cache<TeamList>,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
 ) -> ScrapeResult<TeamList> {
-    let page_raw_html = cache
+    let fetched_page = cache
         .pages
         .retrieve_or_update(
-            Duration::from_secs(TOURNAMENT_EVENT_PLAYER_LIST_PAGES_REFRESH),
+            cache.refresh_interval(
+                tournament_id,
+                cache.refresh.tournament_event_player_list_pages,
+            ),
             event.url.as_str(),
-            |url| async {
+            |url| {
                 client
                     .get(url)
                     .header("Referer", tournament_page_url)
                     .header("Sec-Fetch-Site", "same-origin")
-                    .send()
-                    .await
             },
             "could not load event tournament bracket list",
         )
-        .await?
-        .clone();
-
-    let page = Html::parse_document(&page_raw_html);
-
-    page.select(&SELECTORS.ereport_section)
-        .find(|e| event.name == e.inner_html())
-        .map(|e| {
-            e.next_siblings()
-                .filter_map(ElementRef::wrap)
-                .take_while(|e| &e.value().name.local != "h2")
-                .map(|e| {
-                    e.select(&SELECTORS.ereport_player)
-                        .filter_map(|p| {
-                            name_to_player(&p.inner_html(), tournament_player_list, &page_raw_html)
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .filter(|l| !l.is_empty())
-                .collect::<Vec<_>>()
-        })
-        .ok_or_else(|| ScrapeError::from_str("event not found"))
+        .await?;
+
+    team_list_cache.depend_on(&fetched_page);
+    team_list_cache.derive((tournament_player_list, fetched_page.hash), || {
+        let warnings = ParseWarnings::new(event.url.as_str());
+        let team_list = parse_team_list_ereport(
+            &fetched_page.body,
+            &event.name,
+            tournament_player_list,
+            &warnings,
+        )
+        .ok_or_else(|| ScrapeError::from_str("event not found"))?;
+        team_list_cache.warn(warnings.into_vec());
+        Ok(team_list)
+    })
 }
 
-fn is_round_robin(page: &Html) -> bool {
+pub(super) fn is_round_robin(page: &Html) -> bool {
     page.select(&SELECTORS.table)
         .nth(1)
         .and_then(|t| t.select(&SELECTORS.row).next())
@@ -275,25 +258,147 @@ fn is_round_robin(page: &Html) -> bool {
 }
 
 async fn scrape_team_list_bracket<'a>(
+    tournament_id: usize,
     event: &Event,
     tournament_player_list: &[Player],
+    team_list_cache: &Cache<TeamList>,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
 ) -> ScrapeResult<TeamList> {
-    let page_raw_html = cache
+    let fetched_page = cache
         .pages
         .retrieve_or_update(
-            Duration::from_secs(EVENT_BRACKET_REFRESH),
+            cache.refresh_interval(tournament_id, cache.refresh.event_bracket),
             event.url.as_str(),
-            |url| async { client.get(url).send().await },
+            |url| client.get(url).class(RequestClass::Bracket),
             "could not load event bracket",
         )
-        .await?
-        .clone();
+        .await?;
+
+    team_list_cache.depend_on(&fetched_page);
+    team_list_cache.derive((tournament_player_list, fetched_page.hash), || {
+        let warnings = ParseWarnings::new(event.url.as_str());
+        let team_list =
+            parse_team_list_bracket(&fetched_page.body, tournament_player_list, &warnings);
+        team_list_cache.warn(warnings.into_vec());
+        Ok(team_list)
+    })
+}
 
-    let page = Html::parse_document(&page_raw_html);
+/// Reads a bracket, with its teams resolved against `teams`
+pub fn parse_bracket(bracket_page_html: &str, teams: &[Vec<Player>]) -> Bracket {
+    let page = Html::parse_document(bracket_page_html);
 
     if is_round_robin(&page) {
+        Bracket::RoundRobin(
+            page.select(&SELECTORS.bracket_table)
+                .next()
+                .and_then(|t| t.select(&SELECTORS.row).nth(4))
+                .map(|r| {
+                    r.select(&SELECTORS.cell)
+                        .skip(1)
+                        .step_by(3)
+                        .map(|c| {
+                            std::iter::successors(Some(GridCell(c)), |c| {
+                                c.neighbor(Direction::Down)
+                            })
+                            .step_by(4)
+                            .map(BracketPosition)
+                            .map(BracketNode::crawl_from)
+                            .map(|n| BracketMatch::from_node(&n, teams))
+                            .collect()
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        )
+    } else {
+        Bracket::DoubleElim(
+            page.select(&SELECTORS.bracket_table)
+                .filter_map(|t| {
+                    t.select(&SELECTORS.match_label)
+                        .max_by_key(|l| l.inner_html()[1..].parse::<usize>().unwrap())
+                })
+                .filter_map(|l| l.ancestors().nth(1).and_then(|l| l.next_siblings().nth(1)))
+                .filter_map(ElementRef::wrap)
+                .map(GridCell)
+                .map(BracketPosition)
+                .map(BracketNode::crawl_from)
+                .map(|n| BracketMatch::from_node(&n, teams))
+                .zip(
+                    page.select(&SELECTORS.bracket_name)
+                        .map(|e| Some(e.inner_html()))
+                        .chain(std::iter::repeat(None)),
+                )
+                .map(|(b, n)| (n, b))
+                .collect(),
+        )
+    }
+}
+
+/// Reads the teams from an event's rptbrackets page
+pub fn parse_team_list_rptbrackets(
+    page_raw_html: &str,
+    tournament_player_list: &[Player],
+    warnings: &ParseWarnings,
+) -> TeamList {
+    let page = Html::parse_document(page_raw_html);
+
+    let team_list = page
+        .select(&SELECTORS.rpt_player)
+        .map(|e| e.inner_html())
+        .map(|t| {
+            t.split('/')
+                .filter(|s| !s.trim().is_empty())
+                .filter_map(|s| resolve_player(s, tournament_player_list, page_raw_html, warnings))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    team_list
+}
+
+/// Reads the teams of the event called `event_name` from an ereport page, if it's there
+pub fn parse_team_list_ereport(
+    page_raw_html: &str,
+    event_name: &str,
+    tournament_player_list: &[Player],
+    warnings: &ParseWarnings,
+) -> Option<TeamList> {
+    let page = Html::parse_document(page_raw_html);
+
+    page.select(&SELECTORS.ereport_section)
+        .find(|e| event_name == e.inner_html())
+        .map(|e| {
+            e.next_siblings()
+                .filter_map(ElementRef::wrap)
+                .take_while(|e| &e.value().name.local != "h2")
+                .map(|e| {
+                    e.select(&SELECTORS.ereport_player)
+                        .filter_map(|p| {
+                            resolve_player(
+                                &p.inner_html(),
+                                tournament_player_list,
+                                page_raw_html,
+                                warnings,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .filter(|l| !l.is_empty())
+                .collect::<Vec<_>>()
+        })
+}
+
+/// Reads the teams from an event's bracket page
+pub fn parse_team_list_bracket(
+    page_raw_html: &str,
+    tournament_player_list: &[Player],
+    warnings: &ParseWarnings,
+) -> TeamList {
+    let page = Html::parse_document(page_raw_html);
+
+    let team_list = if is_round_robin(&page) {
         let mut players = page
             .select(&SELECTORS.table)
             .nth(1)
@@ -305,7 +410,9 @@ async fn scrape_team_list_bracket<'a>(
                 PATTERNS
                     .player
                     .captures_iter(&t)
-                    .filter_map(|c| name_to_player(&c[1], tournament_player_list, &page_raw_html))
+                    .filter_map(|c| {
+                        resolve_player(&c[1], tournament_player_list, page_raw_html, warnings)
+                    })
                     .collect::<Vec<_>>()
             })
             .filter(|t| !t.is_empty())
@@ -314,6 +421,7 @@ async fn scrape_team_list_bracket<'a>(
         // This would happen if the bracket only displays last names for whatever goddamn reason.
         // Use a player filter that doesn't look for commas.
         if players.is_empty() {
+            warnings.clear();
             players = page
                 .select(&SELECTORS.table)
                 .nth(1)
@@ -330,12 +438,11 @@ async fn scrape_team_list_bracket<'a>(
                         splits
                             .iter()
                             .filter_map(|p| {
-                                name_to_player(p, tournament_player_list, &page_raw_html)
+                                resolve_player(p, tournament_player_list, page_raw_html, warnings)
                             })
                             .collect()
                     } else if splits.len() == 2 {
-                        if let Some(p) = name_to_player(&t, tournament_player_list, &page_raw_html)
-                        {
+                        if let Some(p) = name_to_player(&t, tournament_player_list, page_raw_html) {
                             // Check to see if this is a hyphenated last name.
                             vec![p]
                         } else {
@@ -343,7 +450,12 @@ async fn scrape_team_list_bracket<'a>(
                             splits
                                 .iter()
                                 .filter_map(|p| {
-                                    name_to_player(p, tournament_player_list, &page_raw_html)
+                                    resolve_player(
+                                        p,
+                                        tournament_player_list,
+                                        page_raw_html,
+                                        warnings,
+                                    )
                                 })
                                 .collect()
                         }
@@ -352,28 +464,33 @@ async fn scrape_team_list_bracket<'a>(
                         let first_joined = [splits[..2].join("-"), splits[2].to_owned()]
                             .iter()
                             .filter_map(|p| {
-                                name_to_player(p, tournament_player_list, &page_raw_html)
+                                name_to_player(p, tournament_player_list, page_raw_html)
                             })
                             .collect::<Vec<_>>();
 
                         let second_joined = [splits[0].to_owned(), splits[1..].join("-")]
                             .iter()
                             .filter_map(|p| {
-                                name_to_player(p, tournament_player_list, &page_raw_html)
+                                name_to_player(p, tournament_player_list, page_raw_html)
                             })
                             .collect::<Vec<_>>();
 
-                        if first_joined.len() >= second_joined.len() {
+                        let team = if first_joined.len() >= second_joined.len() {
                             first_joined
                         } else {
                             second_joined
+                        };
+
+                        if team.len() < 2 {
+                            warnings.warn(ParseWarningKind::UnmatchedPlayer, t.trim());
                         }
+                        team
                     } else {
                         // Apparently both names are hyphenated.
                         [splits[..2].join("-"), splits[2..].join("-")]
                             .iter()
                             .filter_map(|p| {
-                                name_to_player(p, tournament_player_list, &page_raw_html)
+                                resolve_player(p, tournament_player_list, page_raw_html, warnings)
                             })
                             .collect::<Vec<_>>()
                     }
@@ -382,10 +499,9 @@ async fn scrape_team_list_bracket<'a>(
                 .collect();
         }
 
-        Ok(players)
+        players
     } else {
-        Ok(page
-            .select(&SELECTORS.bracket_table)
+        page.select(&SELECTORS.bracket_table)
             .flat_map(|t| {
                 t.select(&SELECTORS.row)
                     .skip(1)
@@ -407,14 +523,57 @@ async fn scrape_team_list_bracket<'a>(
                             .player
                             .captures_iter(&t)
                             .filter_map(|c| {
-                                name_to_player(&c[1], tournament_player_list, &page_raw_html)
+                                resolve_player(
+                                    &c[1],
+                                    tournament_player_list,
+                                    page_raw_html,
+                                    warnings,
+                                )
                             })
                             .collect::<Vec<_>>()
                     })
                     .filter(|t| !t.is_empty())
             })
-            .collect())
-    }
+            .collect()
+    };
+
+    team_list
+}
+
+/// What the health check looks for on an rptbrackets page
+pub fn rptbrackets_checks() -> Vec<Check> {
+    vec![
+        Check::selector("rpt_player", &SELECTORS.rpt_player),
+        Check::pattern("name", &PATTERNS.name),
+    ]
+}
+
+/// What the health check looks for on an ereport page
+pub fn ereport_checks() -> Vec<Check> {
+    vec![
+        Check::selector("ereport_section", &SELECTORS.ereport_section),
+        Check::selector("ereport_player", &SELECTORS.ereport_player),
+        Check::pattern("name", &PATTERNS.name),
+    ]
+}
+
+/// What the health check looks for on a bracket page
+pub fn bracket_checks() -> Vec<Check> {
+    vec![
+        Check::selector("table", &SELECTORS.table),
+        Check::selector("bracket_table", &SELECTORS.bracket_table),
+        Check::selector("row", &SELECTORS.row),
+        Check::selector("cell", &SELECTORS.cell),
+        // Round robins don't have these.
+        Check::selector("bracket_name", &SELECTORS.bracket_name).optional(),
+        Check::selector("match_label", &SELECTORS.match_label).optional(),
+        Check::selector("match_link", &SELECTORS.match_link).optional(),
+        Check::pattern("player", &PATTERNS.player),
+        Check::pattern("borders", &PATTERNS.borders),
+        // Nothing has been played in a bracket that doesn't have these yet.
+        Check::pattern("scores", &PATTERNS.scores).optional(),
+        Check::pattern("match_link", &PATTERNS.match_link).optional(),
+    ]
 }
 
 struct Selectors {
@@ -478,6 +637,23 @@ fn name_to_player(name: &str, players: &[Player], source: &str) -> Option<Player
     find_player(query, players, None, Some(source)).cloned()
 }
 
+/// Like `name_to_player`, but notes the name in `warnings` if it can't be found
+fn resolve_player(
+    name: &str,
+    players: &[Player],
+    source: &str,
+    warnings: &ParseWarnings,
+) -> Option<Player> {
+    let player = name_to_player(name, players, source);
+    if player.is_none() {
+        warnings.warn(
+            ParseWarningKind::UnmatchedPlayer,
+            sanitize_name(name).trim(),
+        );
+    }
+    player
+}
+
 fn resolve_team<'a>(text: &str, teams: &'a [Vec<Player>]) -> Option<&'a Vec<Player>> {
     let player_captures = PATTERNS.player.captures_iter(text).collect::<Vec<_>>();
 
@@ -730,6 +906,14 @@ pub struct BracketMatch {
 }
 
 impl BracketMatch {
+    fn has_winners(&self) -> bool {
+        !self.winner.is_empty()
+            || self.children.iter().any(|c| match c {
+                BracketMatchChild::Match(m) => m.has_winners(),
+                BracketMatchChild::Seed(_) => false,
+            })
+    }
+
     fn from_node(node: &BracketNode, teams: &[Vec<Player>]) -> Self {
         let id = node
             .current
@@ -0,0 +1,238 @@
+//! Runs the parsers over the pages saved under `fixtures/pages`, and compares what they read
+//! against the snapshots under `fixtures/snapshots`.  After a parser changes on purpose, run the
+//! tests with `UPDATE_SNAPSHOTS=1` to write new snapshots, and look over the difference.
+//!
+//! The pages checked in so far were written by hand to look like the upstream's, so they only show
+//! that the parsers haven't changed, not that they read the real site.  Replace them with real
+//! pages by running `pickleballtournamentsportal save-fixtures TOURNAMENT_ID...` with tournaments
+//! that have round robin and elimination brackets, then update the snapshots.  The tests don't
+//! depend on anything in particular being on the pages.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use reqwest::Url;
+use rocket::serde::json::{serde_json, Value};
+use rocket::serde::Serialize;
+
+use crate::client::Upstream;
+use crate::scrape::event::{
+    parse_bracket, parse_team_list_bracket, parse_team_list_ereport, parse_team_list_rptbrackets,
+};
+use crate::scrape::tournament_event_group_list::{
+    parse_event_bracket_list, parse_event_list_urls, parse_event_player_list, EventGroup, EventUrl,
+};
+use crate::scrape::tournament_info::parse_info;
+use crate::scrape::tournament_list::parse_tournament_list;
+use crate::scrape::tournament_player_list::{parse_player_list, PlayerList};
+use crate::scrape::tournament_schedule::parse_schedule;
+use crate::scrape::ParseWarnings;
+
+const PAGE_URL: &str = "https://www.pickleballtournaments.com/fixture";
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+fn page(name: &str) -> String {
+    let path = fixtures_dir().join("pages").join(name);
+    fs::read_to_string(&path).unwrap_or_else(|e| panic!("could not read {:?}: {}", path, e))
+}
+
+fn upstream() -> Upstream {
+    Upstream::new(Url::parse("https://www.pickleballtournaments.com/").unwrap())
+}
+
+fn player_list() -> PlayerList {
+    parse_player_list(&page("tournament_page.html"))
+}
+
+/// Compares `value` against the snapshot called `name`, or replaces the snapshot with it
+fn assert_snapshot(name: &str, value: impl Serialize) {
+    let path = fixtures_dir()
+        .join("snapshots")
+        .join(name)
+        .with_extension("json");
+    let actual = serde_json::to_string_pretty(&value).unwrap() + "\n";
+
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "could not read {:?}: {}.  Run with UPDATE_SNAPSHOTS=1 to create it.",
+            path, e
+        )
+    });
+    assert!(
+        actual == expected,
+        "{} doesn't match its snapshot.  Run with UPDATE_SNAPSHOTS=1 to update it.\n\
+         expected:\n{}\nactual:\n{}",
+        name,
+        expected,
+        actual
+    );
+}
+
+/// Event groups don't serialize their events' URLs, and the events of a group parsed from the
+/// Events/Brackets page come in no particular order, so they're snapshotted through this instead.
+fn event_groups_value(event_groups: &[EventGroup]) -> Value {
+    event_groups
+        .iter()
+        .map(|g| {
+            let mut events = g
+                .events
+                .iter()
+                .map(|e| {
+                    let (kind, url) = match &e.url {
+                        EventUrl::Bracket(url) => ("bracket", url),
+                        EventUrl::GroupList(url) => ("groupList", url),
+                        EventUrl::List(url) => ("list", url),
+                    };
+                    serde_json::json!({ "name": e.name, kind: url })
+                })
+                .collect::<Vec<_>>();
+            events.sort_by_key(|e| e["name"].as_str().unwrap().to_owned());
+
+            serde_json::json!({ "name": g.name, "events": events })
+        })
+        .collect()
+}
+
+#[test]
+fn tournament_list() {
+    let warnings = ParseWarnings::new(PAGE_URL);
+    let tournaments = parse_tournament_list(&page("tournament_list.html"), &upstream(), &warnings);
+
+    assert_snapshot(
+        "tournament_list",
+        serde_json::json!({ "tournaments": tournaments, "warnings": warnings.into_vec() }),
+    );
+}
+
+#[test]
+fn tournament_info() {
+    assert_snapshot("tournament_info", parse_info(&page("tournament_page.html")));
+}
+
+#[test]
+fn tournament_player_list() {
+    assert_snapshot("tournament_player_list", player_list());
+}
+
+#[test]
+fn tournament_schedule() {
+    let event_groups = parse_event_bracket_list(
+        &page("event_bracket_list.html"),
+        &upstream(),
+        &ParseWarnings::new(PAGE_URL),
+    );
+    let warnings = ParseWarnings::new(PAGE_URL);
+    let schedule = parse_schedule(
+        &page("tournament_page.html"),
+        &event_groups,
+        &upstream(),
+        &warnings,
+    );
+
+    assert_snapshot(
+        "tournament_schedule",
+        serde_json::json!({ "schedule": schedule, "warnings": warnings.into_vec() }),
+    );
+}
+
+#[test]
+fn event_bracket_list() {
+    let warnings = ParseWarnings::new(PAGE_URL);
+    let event_groups =
+        parse_event_bracket_list(&page("event_bracket_list.html"), &upstream(), &warnings);
+
+    assert_snapshot(
+        "event_bracket_list",
+        serde_json::json!({
+            "eventGroups": event_groups_value(&event_groups),
+            "warnings": warnings.into_vec(),
+        }),
+    );
+}
+
+#[test]
+fn event_player_list() {
+    let warnings = ParseWarnings::new(PAGE_URL);
+    let event_list_urls =
+        parse_event_list_urls(&page("tournament_page.html"), &upstream(), &warnings);
+    let (name, url) = event_list_urls[0].clone();
+    let event_group = parse_event_player_list(name, url, &page("ereport.html"));
+
+    assert_snapshot(
+        "event_player_list",
+        serde_json::json!({
+            "eventListUrls": event_list_urls,
+            "eventGroup": event_groups_value(&[event_group]),
+            "warnings": warnings.into_vec(),
+        }),
+    );
+}
+
+#[test]
+fn team_list_rptbrackets() {
+    let warnings = ParseWarnings::new(PAGE_URL);
+    let teams = parse_team_list_rptbrackets(&page("rptbrackets.html"), &player_list(), &warnings);
+
+    assert_snapshot(
+        "team_list_rptbrackets",
+        serde_json::json!({ "teams": teams, "warnings": warnings.into_vec() }),
+    );
+}
+
+#[test]
+fn team_list_ereport() {
+    let html = page("ereport.html");
+    let players = player_list();
+    let warnings = ParseWarnings::new(PAGE_URL);
+
+    let events = parse_event_player_list(String::new(), String::new(), &html)
+        .events
+        .into_iter()
+        .map(|e| {
+            let teams = parse_team_list_ereport(&html, &e.name, &players, &warnings);
+            (e.name, teams)
+        })
+        .collect::<Vec<_>>();
+    let missing = parse_team_list_ereport(&html, "No Such Event", &players, &warnings);
+
+    assert!(missing.is_none());
+    assert_snapshot(
+        "team_list_ereport",
+        serde_json::json!({ "events": events, "warnings": warnings.into_vec() }),
+    );
+}
+
+#[test]
+fn double_elim_bracket() {
+    let html = page("bracket_double_elim.html");
+    let warnings = ParseWarnings::new(PAGE_URL);
+    let teams = parse_team_list_bracket(&html, &player_list(), &warnings);
+    let bracket = parse_bracket(&html, &teams);
+
+    assert_snapshot(
+        "double_elim_bracket",
+        serde_json::json!({ "teams": teams, "bracket": bracket, "warnings": warnings.into_vec() }),
+    );
+}
+
+#[test]
+fn round_robin_bracket() {
+    let html = page("bracket_round_robin.html");
+    let warnings = ParseWarnings::new(PAGE_URL);
+    let teams = parse_team_list_bracket(&html, &player_list(), &warnings);
+    let bracket = parse_bracket(&html, &teams);
+
+    assert_snapshot(
+        "round_robin_bracket",
+        serde_json::json!({ "teams": teams, "bracket": bracket, "warnings": warnings.into_vec() }),
+    );
+}
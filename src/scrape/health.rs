@@ -0,0 +1,630 @@
+//! Checks that the selectors and patterns the scrapers rely on still find what they're looking for,
+//! so that we hear about markup changes on pickleballtournaments.com before visitors do.  The checks
+//! are run against the tournament list and the pages of a few reference tournaments, either as they
+//! were last saved or fetched again.
+
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+
+use regex::Regex;
+use reqwest::Url;
+use rocket::serde::Serialize;
+use scraper::{Html, Selector};
+
+use crate::client::{Client, RequestBuilder, RequestClass};
+use crate::scrape::event::{self, Bracket};
+use crate::scrape::tournament_event_group_list::{self, EventGroup, EventUrl};
+use crate::scrape::tournament_player_list::{self, PlayerList};
+use crate::scrape::{tournament_info, tournament_list, tournament_schedule};
+use crate::scrape::{ParseWarning, ParseWarnings, ScrapeCache, ScrapeResult};
+use crate::util::cache::{CacheSnapshot, Page};
+
+/// A selector or pattern that a scraper expects to find on a page
+pub struct Check {
+    name: &'static str,
+    matcher: Matcher,
+    /// Whether the page is broken if this matches nothing.  Some things only show up on some pages.
+    required: bool,
+}
+
+enum Matcher {
+    Selector(&'static Selector),
+    /// A pattern, along with the parts of the page it's applied to
+    Pattern(&'static Regex, fn(&Html) -> Vec<String>),
+}
+
+impl Check {
+    pub fn selector(name: &'static str, selector: &'static Selector) -> Self {
+        Self {
+            name,
+            matcher: Matcher::Selector(selector),
+            required: true,
+        }
+    }
+
+    /// A pattern that's applied to the markup of the whole page
+    pub fn pattern(name: &'static str, pattern: &'static Regex) -> Self {
+        Self::pattern_in(name, pattern, |page| vec![page.root_element().html()])
+    }
+
+    /// A pattern that's applied to the parts of the page picked out by `parts`, for patterns that
+    /// are anchored to the text they're given
+    pub fn pattern_in(
+        name: &'static str,
+        pattern: &'static Regex,
+        parts: fn(&Html) -> Vec<String>,
+    ) -> Self {
+        Self {
+            name,
+            matcher: Matcher::Pattern(pattern, parts),
+            required: true,
+        }
+    }
+
+    pub fn optional(self) -> Self {
+        Self {
+            required: false,
+            ..self
+        }
+    }
+
+    fn kind(&self) -> MatchKind {
+        match self.matcher {
+            Matcher::Selector(_) => MatchKind::Selector,
+            Matcher::Pattern(..) => MatchKind::Pattern,
+        }
+    }
+
+    fn count(&self, page: &Html) -> usize {
+        match self.matcher {
+            Matcher::Selector(selector) => page.select(selector).count(),
+            Matcher::Pattern(pattern, parts) => parts(page)
+                .iter()
+                .map(|p| pattern.find_iter(p).count())
+                .sum(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pages: Vec<PageHealth>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.pages
+            .iter()
+            .all(|p| p.error.is_none() && p.problems.is_empty())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct PageHealth {
+    kind: PageKind,
+    url: String,
+    /// Why the page couldn't be checked, if it couldn't
+    error: Option<String>,
+    matches: Vec<MatchCount>,
+    /// Required checks that matched nothing, and parsed output that doesn't look right
+    problems: Vec<String>,
+    warnings: Vec<ParseWarning>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct MatchCount {
+    kind: MatchKind,
+    /// The scraper and the name of its selector or pattern, e.g. `tournament_list::title`
+    name: String,
+    matches: usize,
+    required: bool,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub enum MatchKind {
+    Selector,
+    Pattern,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub enum PageKind {
+    TournamentList,
+    TournamentPage,
+    EventBracketList,
+    EventPlayerList,
+    EventRptBrackets,
+    EventReport,
+    EventBracket,
+}
+
+impl ScrapeCache {
+    /// Runs the scrapers against the tournament list and the pages of `tournament_ids`, or of the
+    /// most recent past tournament if there are none, since its brackets should be complete.  Pages
+    /// are taken from the cache or page store where they can be, unless `fresh` is set.
+    pub async fn check_health(
+        &self,
+        tournament_ids: &[usize],
+        fresh: bool,
+        client: &Client<'_>,
+    ) -> HealthReport {
+        let mut check = HealthCheck {
+            cache: self,
+            client,
+            fresh,
+            pages: Vec::new(),
+        };
+
+        let latest_past_tournament = check.tournament_list().await;
+
+        let tournament_ids = if tournament_ids.is_empty() {
+            latest_past_tournament.into_iter().collect()
+        } else {
+            tournament_ids.to_vec()
+        };
+
+        for tournament_id in tournament_ids {
+            check.tournament(tournament_id).await;
+        }
+
+        HealthReport { pages: check.pages }
+    }
+}
+
+struct HealthCheck<'a> {
+    cache: &'a ScrapeCache,
+    client: &'a Client<'a>,
+    fresh: bool,
+    pages: Vec<PageHealth>,
+}
+
+impl<'a> HealthCheck<'a> {
+    /// Checks both tournament list pages, returning the id of the most recent past tournament
+    async fn tournament_list(&mut self) -> Option<usize> {
+        let future_page_url = self.client.upstream().url("pbt_tlisting.pl?when=F");
+        let past_page_url = self.client.upstream().url("pbt_tlisting.pl?when=P");
+
+        let mut latest_past_tournament = None;
+
+        let client = self.client;
+
+        for url in [&future_page_url, &past_page_url] {
+            let is_past_page = url == &past_page_url;
+
+            let page = self
+                .load(url, |url| {
+                    let request = client.get(url).class(RequestClass::Listing);
+                    if is_past_page {
+                        request
+                            .header("Referer", &future_page_url)
+                            .header("Sec-Fetch-Site", "same-origin")
+                    } else {
+                        request
+                    }
+                })
+                .await;
+
+            let listings = self.check_page(
+                PageKind::TournamentList,
+                url,
+                page,
+                vec![("tournament_list", tournament_list::checks())],
+                |html, warnings, problems| {
+                    let listings =
+                        tournament_list::parse_tournament_list(html, client.upstream(), warnings);
+
+                    if listings.is_empty() {
+                        problems.push("no tournaments".to_owned());
+                    } else if listings.iter().all(|t| {
+                        matches!(
+                            t.registration_status,
+                            tournament_list::RegistrationStatus::Unknown { .. }
+                        )
+                    }) {
+                        problems.push("no registration statuses could be read".to_owned());
+                    }
+
+                    listings
+                },
+            );
+
+            if is_past_page {
+                latest_past_tournament = listings
+                    .unwrap_or_default()
+                    .into_iter()
+                    .max_by_key(|t| t.end_date)
+                    .map(|t| t.id);
+            }
+        }
+
+        latest_past_tournament
+    }
+
+    async fn tournament(&mut self, tournament_id: usize) {
+        let client = self.client;
+        let upstream = client.upstream();
+        let tournament_page_url = upstream.url(&format!("tournamentinfo.pl?tid={}", tournament_id));
+        let event_bracket_page_url = upstream.url(&format!("cinfo.pl?tid={}", tournament_id));
+
+        let page = self
+            .load(&event_bracket_page_url, |url| {
+                client
+                    .get(url)
+                    .header("Referer", &tournament_page_url)
+                    .header("Sec-Fetch-Site", "same-origin")
+            })
+            .await;
+        let mut event_groups = self
+            .check_page(
+                PageKind::EventBracketList,
+                &event_bracket_page_url,
+                page,
+                vec![(
+                    "tournament_event_group_list",
+                    tournament_event_group_list::event_bracket_list_checks(),
+                )],
+                |html, warnings, problems| {
+                    let event_groups = tournament_event_group_list::parse_event_bracket_list(
+                        html, upstream, warnings,
+                    );
+                    check_event_groups(&event_groups, problems);
+                    event_groups
+                },
+            )
+            .unwrap_or_default();
+
+        let page = self.load(&tournament_page_url, |url| client.get(url)).await;
+        let (player_list, event_list_urls) = self
+            .check_page(
+                PageKind::TournamentPage,
+                &tournament_page_url,
+                page,
+                vec![
+                    ("tournament_info", tournament_info::checks()),
+                    ("tournament_player_list", tournament_player_list::checks()),
+                    ("tournament_schedule", tournament_schedule::checks()),
+                    (
+                        "tournament_event_group_list",
+                        tournament_event_group_list::tournament_page_checks(),
+                    ),
+                ],
+                |html, warnings, problems| {
+                    if tournament_info::parse_info(html).is_empty() {
+                        problems.push("no info tabs".to_owned());
+                    }
+
+                    let player_list = tournament_player_list::parse_player_list(html);
+                    if player_list.is_empty() {
+                        problems.push("no players".to_owned());
+                    }
+
+                    tournament_schedule::parse_schedule(html, &event_groups, upstream, warnings);
+
+                    (
+                        player_list,
+                        tournament_event_group_list::parse_event_list_urls(
+                            html, upstream, warnings,
+                        ),
+                    )
+                },
+            )
+            .unwrap_or_default();
+
+        // The scrapers only fall back to the Event Player List pages if the Events/Brackets page is empty.
+        if event_groups.is_empty() {
+            for (name, url) in event_list_urls {
+                let page = self
+                    .load(&url, |url| {
+                        client
+                            .get(url)
+                            .header("Referer", &tournament_page_url)
+                            .header("Sec-Fetch-Site", "same-origin")
+                    })
+                    .await;
+                let event_group = self.check_page(
+                    PageKind::EventPlayerList,
+                    &url,
+                    page,
+                    vec![(
+                        "tournament_event_group_list",
+                        tournament_event_group_list::event_player_list_checks(),
+                    )],
+                    |html, _, problems| {
+                        let event_group = tournament_event_group_list::parse_event_player_list(
+                            name,
+                            url.clone(),
+                            html,
+                        );
+                        check_event_groups(std::slice::from_ref(&event_group), problems);
+                        event_group
+                    },
+                );
+                event_groups.extend(event_group);
+            }
+        }
+
+        // Events of the same kind share a page layout, so one of each is enough.
+        let events = event_groups.iter().flat_map(|g| g.events.iter());
+        let rptbrackets_event = events.clone().find(|e| matches!(e.url, EventUrl::List(_)));
+        let ereport_event = events
+            .clone()
+            .find(|e| matches!(e.url, EventUrl::GroupList(_)));
+        let bracket_event = events
+            .clone()
+            .find(|e| matches!(e.url, EventUrl::Bracket(_)));
+
+        if let Some(event) = rptbrackets_event {
+            let url = event.url.as_str();
+            let page = self.load(url, |url| client.get(url)).await;
+            self.check_page(
+                PageKind::EventRptBrackets,
+                url,
+                page,
+                vec![("event", event::rptbrackets_checks())],
+                |html, warnings, problems| {
+                    let team_list =
+                        event::parse_team_list_rptbrackets(html, &player_list, warnings);
+                    check_team_list(&team_list, problems);
+                },
+            );
+        }
+
+        if let Some(event) = ereport_event {
+            let url = event.url.as_str();
+            let page = self
+                .load(url, |url| {
+                    client
+                        .get(url)
+                        .header("Referer", &tournament_page_url)
+                        .header("Sec-Fetch-Site", "same-origin")
+                })
+                .await;
+            self.check_page(
+                PageKind::EventReport,
+                url,
+                page,
+                vec![("event", event::ereport_checks())],
+                |html, warnings, problems| match event::parse_team_list_ereport(
+                    html,
+                    &event.name,
+                    &player_list,
+                    warnings,
+                ) {
+                    Some(team_list) => check_team_list(&team_list, problems),
+                    None => problems.push(format!("event {:?} isn't on its page", event.name)),
+                },
+            );
+        }
+
+        if let Some(event) = bracket_event {
+            let url = event.url.as_str();
+            let page = self
+                .load(url, |url| client.get(url).class(RequestClass::Bracket))
+                .await;
+            self.check_page(
+                PageKind::EventBracket,
+                url,
+                page,
+                vec![("event", event::bracket_checks())],
+                |html, warnings, problems| {
+                    let team_list = event::parse_team_list_bracket(html, &player_list, warnings);
+                    check_team_list(&team_list, problems);
+
+                    let bracket = event::parse_bracket(html, &team_list);
+                    check_bracket(&bracket, problems);
+                },
+            );
+        }
+    }
+
+    /// Gets a page from the cache, or from pickleballtournaments.com if it isn't cached or the
+    /// check is supposed to be fresh
+    async fn load<'c>(
+        &self,
+        url: &str,
+        request: impl Fn(Url) -> RequestBuilder<'c>,
+    ) -> ScrapeResult<CacheSnapshot<Page>> {
+        let interval = if self.fresh {
+            Duration::ZERO
+        } else {
+            Duration::MAX
+        };
+
+        self.cache
+            .pages
+            .retrieve_or_update(
+                interval,
+                url,
+                request,
+                "could not load page for health check",
+            )
+            .await
+    }
+
+    /// Runs `checks` against a loaded page, and then `parse`, which should note anything that
+    /// looks wrong with what it parsed in its problems.  Returns what was parsed, unless the
+    /// page couldn't be loaded or the parser panicked.
+    fn check_page<R>(
+        &mut self,
+        kind: PageKind,
+        url: &str,
+        page: ScrapeResult<CacheSnapshot<Page>>,
+        checks: Vec<(&'static str, Vec<Check>)>,
+        parse: impl FnOnce(&str, &ParseWarnings, &mut Vec<String>) -> R,
+    ) -> Option<R> {
+        let mut health = PageHealth {
+            kind,
+            url: url.to_owned(),
+            error: None,
+            matches: Vec::new(),
+            problems: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        let page = match page {
+            Ok(page) => page,
+            Err(error) => {
+                health.error = Some(error.to_string());
+                self.pages.push(health);
+                return None;
+            }
+        };
+
+        let document = Html::parse_document(&page.body);
+        for (scraper, checks) in checks {
+            for check in checks {
+                let matches = check.count(&document);
+                let name = format!("{}::{}", scraper, check.name);
+
+                if matches == 0 && check.required {
+                    health
+                        .problems
+                        .push(format!("{} {} matched nothing", check.kind(), name));
+                }
+
+                health.matches.push(MatchCount {
+                    kind: check.kind(),
+                    name,
+                    matches,
+                    required: check.required,
+                });
+            }
+        }
+
+        let warnings = ParseWarnings::new(url);
+        let problems = &mut health.problems;
+        let parsed =
+            panic::catch_unwind(AssertUnwindSafe(|| parse(&page.body, &warnings, problems)));
+
+        if let Err(panic) = &parsed {
+            health
+                .problems
+                .push(format!("parser panicked: {}", panic_message(&**panic)));
+        }
+
+        health.warnings = warnings.into_vec();
+        self.pages.push(health);
+
+        parsed.ok()
+    }
+}
+
+fn check_event_groups(event_groups: &[EventGroup], problems: &mut Vec<String>) {
+    if event_groups.is_empty() {
+        problems.push("no event groups".to_owned());
+    }
+
+    for event_group in event_groups.iter().filter(|g| g.events.is_empty()) {
+        problems.push(format!("event group {:?} has no events", event_group.name));
+    }
+}
+
+fn check_team_list(team_list: &[PlayerList], problems: &mut Vec<String>) {
+    if team_list.is_empty() {
+        problems.push("no teams".to_owned());
+    } else if team_list.iter().any(PlayerList::is_empty) {
+        problems.push("teams without any players".to_owned());
+    }
+}
+
+fn check_bracket(bracket: &Bracket, problems: &mut Vec<String>) {
+    if bracket.is_empty() {
+        problems.push("no matches".to_owned());
+    } else if !bracket.has_winners() {
+        problems.push("no winners".to_owned());
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown"
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for page in &self.pages {
+            writeln!(f, "{} {}", page.kind, page.url)?;
+
+            if let Some(error) = &page.error {
+                writeln!(f, "  error: {}", error)?;
+                continue;
+            }
+
+            for count in &page.matches {
+                let flag = if count.matches == 0 && count.required {
+                    " !"
+                } else {
+                    ""
+                };
+                writeln!(
+                    f,
+                    "  {:>5} {:<8} {}{}",
+                    count.matches, count.kind, count.name, flag
+                )?;
+            }
+
+            for problem in &page.problems {
+                writeln!(f, "  problem: {}", problem)?;
+            }
+
+            for warning in &page.warnings {
+                writeln!(f, "  warning: {:?}: {}", warning.kind, warning.raw)?;
+            }
+        }
+
+        let unhealthy = self
+            .pages
+            .iter()
+            .filter(|p| p.error.is_some() || !p.problems.is_empty())
+            .count();
+
+        match unhealthy {
+            0 => write!(f, "all {} pages look healthy", self.pages.len()),
+            _ => write!(
+                f,
+                "{} of {} pages have problems",
+                unhealthy,
+                self.pages.len()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MatchKind::Selector => "selector",
+            MatchKind::Pattern => "pattern",
+        })
+    }
+}
+
+impl fmt::Display for PageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PageKind::TournamentList => "tournament list",
+            PageKind::TournamentPage => "tournament page",
+            PageKind::EventBracketList => "event bracket list",
+            PageKind::EventPlayerList => "event player list",
+            PageKind::EventRptBrackets => "event rptbrackets",
+            PageKind::EventReport => "event ereport",
+            PageKind::EventBracket => "event bracket",
+        })
+    }
+}
@@ -1,25 +1,52 @@
-use crate::util::cache::{Cache, CacheMap, PageCache};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::prelude::*;
+use rocket::fairing::AdHoc;
+use rocket::serde::Deserialize;
+
+use crate::util::cache::{Cache, CacheLimits, CacheMap, PageCache};
+use crate::util::clock::{ScaledClock, SharedClock, SystemClock};
+use crate::util::page_store::PageStore;
 
 use self::event::{Bracket, TeamList};
 use self::tournament_event_group_list::EventGroupList;
 use self::tournament_info::Info;
-use self::tournament_list::TournamentList;
+use self::tournament_list::{RegistrationStatus, TournamentList, TournamentPhase};
 use self::tournament_player_list::PlayerList;
 use self::tournament_schedule::Schedule;
 
-pub use self::result::{scrape_result, ScrapeError, ScrapeResult};
+pub use self::result::{scrape_body, scrape_result, ScrapeError, ScrapeResult};
+pub use self::warning::{ParseWarning, ParseWarningKind, ParseWarnings};
 
+pub mod admin;
+pub mod capture;
 pub mod event;
+pub mod health;
 pub mod tournament_event_group_list;
 pub mod tournament_info;
 pub mod tournament_list;
 pub mod tournament_player_list;
 pub mod tournament_schedule;
+pub mod warming;
 
+#[cfg(test)]
+mod fixtures;
 mod result;
+mod warning;
+
+/// How often caches are swept for entries that have gone unused for longer than their idle limit
+const IDLE_EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A cheaply cloneable handle to the scrape caches, so that background tasks can hold onto them
+#[derive(Clone)]
+pub struct ScrapeCache(Arc<Caches>);
 
-#[derive(Default)]
-pub struct ScrapeCache {
+pub struct Caches {
+    pub refresh: RefreshIntervals,
+    clock: SharedClock,
     event_bracket: CacheMap<(usize, String), Bracket>,
     event_team_list: CacheMap<(usize, String), TeamList>,
     pages: PageCache,
@@ -30,13 +57,346 @@ pub struct ScrapeCache {
     tournament_schedule: CacheMap<usize, Schedule>,
 }
 
-const EVENT_BRACKET_REFRESH: u64 = 2 * 60;
-const EVENT_TEAM_LIST_REFRESH: u64 = 3 * 60 * 60;
-const TOURNAMENT_LIST_REFRESH: u64 = 3 * 60 * 60;
-const TOURNAMENT_EVENT_BRACKET_PAGE_REFRESH: u64 = 15 * 60;
-const TOURNAMENT_EVENT_LIST_REFRESH: u64 = 15 * 60;
-const TOURNAMENT_EVENT_PLAYER_LIST_PAGES_REFRESH: u64 = 3 * 60 * 60;
-const TOURNAMENT_INFO_REFRESH: u64 = 3 * 60 * 60;
-const TOURNAMENT_PAGE_REFRESH: u64 = 60 * 60;
-const TOURNAMENT_PLAYER_LIST_REFRESH: u64 = 3 * 60 * 60;
-const TOURNAMENT_SCHEDULE_REFRESH: u64 = 15 * 60;
+impl ScrapeCache {
+    /// Creates a cache bounded by `limits`, which expires entries by `clock`.  If there is a page
+    /// store, pages are persisted to it and any pages saved by a previous run are picked up.
+    pub async fn new(
+        refresh: RefreshIntervals,
+        limits: ScrapeCacheLimits,
+        max_page_size: usize,
+        page_store: Option<PageStore>,
+        clock: SharedClock,
+    ) -> Self {
+        let pages = match page_store {
+            Some(store) => {
+                let max_age = Duration::from_secs(refresh.longest_page_refresh());
+                PageCache::with_store(limits.pages, max_page_size, store, max_age, clock.clone())
+                    .await
+            }
+            None => PageCache::new(limits.pages, max_page_size, clock.clone()),
+        };
+
+        Self(Arc::new(Caches {
+            refresh,
+            clock: clock.clone(),
+            event_bracket: CacheMap::with_limits(limits.derived, clock.clone()),
+            event_team_list: CacheMap::with_limits(limits.derived, clock.clone()),
+            pages,
+            tournament_list: Cache::new(clock.clone()),
+            tournament_event_list: CacheMap::with_limits(limits.derived, clock.clone()),
+            tournament_info: CacheMap::with_limits(limits.derived, clock.clone()),
+            tournament_player_list: CacheMap::with_limits(limits.derived, clock.clone()),
+            tournament_schedule: CacheMap::with_limits(limits.derived, clock.clone()),
+        }))
+    }
+
+    /// Picks how often to refresh something that belongs to a tournament, given how often it would be
+    /// refreshed while the tournament is being played.  Nothing changes once a tournament is over,
+    /// and little changes before it starts, especially before registration opens.  Tournaments that
+    /// aren't in the cached tournament list are treated as ongoing.
+    pub fn refresh_interval(&self, tournament_id: usize, ongoing_interval: u64) -> Duration {
+        let today = self.today();
+
+        let lifecycle_interval = match self.tournament_list.peek() {
+            Some(tournament_list) => tournament_list
+                .iter()
+                .find(|t| t.id == tournament_id)
+                .and_then(|t| match (t.phase(today), &t.registration_status) {
+                    (TournamentPhase::Past, _) => Some(self.refresh.past_tournament),
+                    (
+                        TournamentPhase::Upcoming,
+                        RegistrationStatus::NotOpen | RegistrationStatus::OpenSoon { .. },
+                    ) => Some(self.refresh.unopened_tournament),
+                    (TournamentPhase::Upcoming, _) => Some(self.refresh.upcoming_tournament),
+                    (TournamentPhase::Ongoing, _) => None,
+                }),
+            None => None,
+        };
+
+        Duration::from_secs(
+            lifecycle_interval.map_or(ongoing_interval, |i| i.max(ongoing_interval)),
+        )
+    }
+
+    /// The local date by the clock the caches expire by
+    pub fn today(&self) -> NaiveDate {
+        DateTime::<Local>::from(self.clock.system_now())
+            .date()
+            .naive_local()
+    }
+
+    /// Removes the entries of every cache that have gone unused for longer than their idle limit
+    pub fn evict_idle(&self) {
+        self.event_bracket.evict_idle();
+        self.event_team_list.evict_idle();
+        self.pages.evict_idle();
+        self.tournament_event_list.evict_idle();
+        self.tournament_info.evict_idle();
+        self.tournament_player_list.evict_idle();
+        self.tournament_schedule.evict_idle();
+    }
+
+    /// Periodically removes idle cache entries once Rocket has launched
+    pub fn eviction_fairing() -> AdHoc {
+        AdHoc::on_liftoff("Cache Eviction", |rocket| {
+            Box::pin(async move {
+                let cache = match rocket.state::<ScrapeCache>() {
+                    Some(cache) => cache.clone(),
+                    None => {
+                        error!("cache eviction needs the scrape cache to be managed");
+                        return;
+                    }
+                };
+
+                let shutdown = rocket.shutdown();
+
+                rocket::tokio::spawn(async move {
+                    let evict_periodically = async {
+                        loop {
+                            async_std::task::sleep(IDLE_EVICTION_INTERVAL).await;
+                            cache.evict_idle();
+                        }
+                    };
+
+                    rocket::tokio::select! {
+                        _ = evict_periodically => {},
+                        _ = shutdown => {},
+                    }
+                });
+            })
+        })
+    }
+
+    /// Builds the cache from the Rocket configuration and puts it in managed state
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Scrape Cache", |rocket| async {
+            let config = match rocket.figment().extract::<ScrapeConfig>() {
+                Ok(config) => config,
+                Err(error) => {
+                    error!("invalid scrape configuration: {}", error);
+                    return Err(rocket);
+                }
+            };
+
+            if let Err(error) = config.refresh_intervals.validate() {
+                error!("invalid refresh intervals: {}", error);
+                return Err(rocket);
+            }
+
+            let page_store = match config.page_cache_dir {
+                Some(ref dir) => match PageStore::open(dir).await {
+                    Ok(store) => Some(store),
+                    Err(error) => {
+                        error!("could not open page cache directory {:?}: {}", dir, error);
+                        return Err(rocket);
+                    }
+                },
+                None => None,
+            };
+
+            if config.max_page_size == 0 {
+                error!("max_page_size must be nonzero");
+                return Err(rocket);
+            }
+
+            if !(config.clock_rate.is_finite() && config.clock_rate > 0.0) {
+                error!("clock_rate must be positive");
+                return Err(rocket);
+            }
+
+            let cache = ScrapeCache::new(
+                config.refresh_intervals,
+                config.cache_limits,
+                config.max_page_size,
+                page_store,
+                config.clock(),
+            )
+            .await;
+
+            Ok(rocket.manage(cache))
+        })
+    }
+}
+
+impl Deref for ScrapeCache {
+    type Target = Caches;
+
+    fn deref(&self) -> &Caches {
+        &self.0
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ScrapeConfig {
+    /// Where fetched pages are persisted between runs.  Pages are only kept in memory if this isn't set.
+    #[serde(default)]
+    page_cache_dir: Option<PathBuf>,
+    /// Seconds between cache warming passes over today's tournaments.  Set to 0 to disable warming.
+    /// Defaults to the event bracket refresh interval.
+    #[serde(default)]
+    warm_interval: Option<u64>,
+    /// Bytes a fetched page may take up before it's refused
+    #[serde(default = "default_max_page_size")]
+    max_page_size: usize,
+    #[serde(default)]
+    cache_limits: ScrapeCacheLimits,
+    #[serde(default)]
+    refresh_intervals: RefreshIntervals,
+    /// When the caches' clock starts, for replaying saved pages as if it were then.  Defaults to now.
+    #[serde(default)]
+    clock_start: Option<DateTime<Utc>>,
+    /// How many times faster than real time the caches' clock runs
+    #[serde(default = "default_clock_rate")]
+    clock_rate: f64,
+}
+
+impl ScrapeConfig {
+    fn warm_interval(&self) -> u64 {
+        self.warm_interval
+            .unwrap_or(self.refresh_intervals.event_bracket)
+    }
+
+    fn clock(&self) -> SharedClock {
+        match self.clock_start {
+            None if self.clock_rate == 1.0 => SystemClock::shared(),
+            start => ScaledClock::shared(
+                start.map_or_else(SystemTime::now, SystemTime::from),
+                self.clock_rate,
+            ),
+        }
+    }
+}
+
+fn default_clock_rate() -> f64 {
+    1.0
+}
+
+fn default_max_page_size() -> usize {
+    16 * 1024 * 1024
+}
+
+/// How many seconds each piece of scraped data is kept before it's fetched again.  These apply as-is
+/// to tournaments that are being played, while the `*_tournament` intervals set a floor for the rest.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct RefreshIntervals {
+    pub event_bracket: u64,
+    pub event_team_list: u64,
+    pub tournament_list: u64,
+    pub tournament_event_bracket_page: u64,
+    pub tournament_event_list: u64,
+    pub tournament_event_player_list_pages: u64,
+    pub tournament_info: u64,
+    pub tournament_page: u64,
+    pub tournament_player_list: u64,
+    pub tournament_schedule: u64,
+    pub upcoming_tournament: u64,
+    pub unopened_tournament: u64,
+    pub past_tournament: u64,
+}
+
+impl RefreshIntervals {
+    fn validate(&self) -> Result<(), String> {
+        let intervals = [
+            ("event_bracket", self.event_bracket),
+            ("event_team_list", self.event_team_list),
+            ("tournament_list", self.tournament_list),
+            (
+                "tournament_event_bracket_page",
+                self.tournament_event_bracket_page,
+            ),
+            ("tournament_event_list", self.tournament_event_list),
+            (
+                "tournament_event_player_list_pages",
+                self.tournament_event_player_list_pages,
+            ),
+            ("tournament_info", self.tournament_info),
+            ("tournament_page", self.tournament_page),
+            ("tournament_player_list", self.tournament_player_list),
+            ("tournament_schedule", self.tournament_schedule),
+            ("upcoming_tournament", self.upcoming_tournament),
+            ("unopened_tournament", self.unopened_tournament),
+            ("past_tournament", self.past_tournament),
+        ];
+
+        // Without a refresh interval every request would go to pickleballtournaments.com.
+        match intervals.iter().find(|(_, interval)| *interval == 0) {
+            Some((name, _)) => Err(format!("{} must be at least 1 second", name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Stored pages older than the longest page refresh interval would just be fetched again
+    fn longest_page_refresh(&self) -> u64 {
+        IntoIterator::into_iter([
+            self.event_bracket,
+            self.tournament_list,
+            self.tournament_event_bracket_page,
+            self.tournament_event_player_list_pages,
+            self.tournament_page,
+            self.upcoming_tournament,
+            self.unopened_tournament,
+            self.past_tournament,
+        ])
+        .max()
+        .unwrap()
+    }
+}
+
+impl Default for RefreshIntervals {
+    fn default() -> Self {
+        Self {
+            event_bracket: 2 * 60,
+            event_team_list: 3 * 60 * 60,
+            tournament_list: 3 * 60 * 60,
+            tournament_event_bracket_page: 15 * 60,
+            tournament_event_list: 15 * 60,
+            tournament_event_player_list_pages: 3 * 60 * 60,
+            tournament_info: 3 * 60 * 60,
+            tournament_page: 60 * 60,
+            tournament_player_list: 3 * 60 * 60,
+            tournament_schedule: 15 * 60,
+            upcoming_tournament: 60 * 60,
+            unopened_tournament: 24 * 60 * 60,
+            past_tournament: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ScrapeCacheLimits {
+    /// Limits for the cache of raw pages
+    #[serde(default = "default_page_limits")]
+    pub pages: CacheLimits,
+    /// Limits for each of the caches of data parsed from those pages
+    #[serde(default = "default_derived_limits")]
+    pub derived: CacheLimits,
+}
+
+impl Default for ScrapeCacheLimits {
+    fn default() -> Self {
+        Self {
+            pages: default_page_limits(),
+            derived: default_derived_limits(),
+        }
+    }
+}
+
+fn default_page_limits() -> CacheLimits {
+    CacheLimits {
+        max_entries: None,
+        max_bytes: Some(256 * 1024 * 1024),
+        idle_ttl: Some(24 * 60 * 60),
+    }
+}
+
+fn default_derived_limits() -> CacheLimits {
+    CacheLimits {
+        max_entries: Some(1000),
+        max_bytes: None,
+        idle_ttl: Some(24 * 60 * 60),
+    }
+}
@@ -1,8 +1,14 @@
-use reqwest::{Error, Response};
+use std::fmt;
+
+use encoding_rs::Encoding;
+use reqwest::header::CONTENT_TYPE;
 use rocket::response::Responder;
 use rocket::serde::json::Json;
 use rocket::serde::Serialize;
 
+use crate::client::{is_captcha, Error, Response};
+use crate::util::metrics::METRICS;
+
 pub type ScrapeResult<T> = Result<T, ScrapeError>;
 
 #[derive(Clone, Debug, Serialize)]
@@ -25,6 +31,8 @@ pub enum ScrapeError {
     Captcha(Json<CaptchaPayload>),
     #[response(status = 500)]
     Error(Json<ErrorPayload>),
+    #[response(status = 504)]
+    Timeout(Json<ErrorPayload>),
 }
 
 impl ScrapeError {
@@ -35,22 +43,103 @@ impl ScrapeError {
     }
 }
 
+impl fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScrapeError::Captcha(Json(CaptchaPayload::Captcha { url })) => {
+                write!(f, "captcha required: {}", url)
+            }
+            ScrapeError::Error(Json(ErrorPayload::Error { reason }))
+            | ScrapeError::Timeout(Json(ErrorPayload::Error { reason })) => write!(f, "{}", reason),
+        }
+    }
+}
+
 pub fn scrape_result(response: Result<Response, Error>, error: &str) -> ScrapeResult<Response> {
     match response {
         Ok(r) => {
-            let url = r.url().to_string();
-            if url.contains("validate.perfdrive.com") {
+            if is_captcha(&r) {
+                METRICS.record_captcha(r.request_url());
+                let url = r.url().to_string();
                 Err(ScrapeError::Captcha(Json(CaptchaPayload::Captcha { url })))
+            } else if !r.status().is_success() {
+                Err(ScrapeError::Error(Json(ErrorPayload::Error {
+                    reason: format!("{}:\n  status: {}", error, r.status().as_u16()),
+                })))
             } else {
                 Ok(r)
             }
         }
-        Err(e) => Err(ScrapeError::Error(Json(ErrorPayload::Error {
+        Err(e) => Err(request_error(e, error)),
+    }
+}
+
+/// Reads the body of a successful response, as long as it's no bigger than `max_size` bytes and is
+/// valid in the charset it claims to be in
+pub async fn scrape_body(
+    mut response: Response,
+    max_size: usize,
+    error: &str,
+) -> ScrapeResult<String> {
+    let body_error =
+        |reason: &str| ScrapeError::from_str(&format!("{}:\n  error: {}", error, reason));
+    let too_large = || body_error(&format!("page is larger than {} bytes", max_size));
+
+    if response
+        .content_length()
+        .is_some_and(|l| l > max_size as u64)
+    {
+        return Err(too_large());
+    }
+
+    let charset = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split(';')
+                .filter_map(|p| p.trim().split_once('='))
+                .find(|(k, _)| k.eq_ignore_ascii_case("charset"))
+                .map(|(_, v)| v.trim_matches('"').to_owned())
+        })
+        .unwrap_or_else(|| "utf-8".to_owned());
+
+    let encoding = Encoding::for_label(charset.as_bytes())
+        .ok_or_else(|| body_error(&format!("unknown charset {:?}", charset)))?;
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| request_error(e.into(), error))?
+    {
+        if body.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+
+        body.extend_from_slice(&chunk);
+    }
+
+    let (encoding, bom_length) = Encoding::for_bom(&body).unwrap_or((encoding, 0));
+
+    encoding
+        .decode_without_bom_handling_and_without_replacement(&body[bom_length..])
+        .map(|b| b.into_owned())
+        .ok_or_else(|| body_error(&format!("page is not valid {}", encoding.name())))
+}
+
+fn request_error(e: Error, error: &str) -> ScrapeError {
+    if e.is_timeout() {
+        ScrapeError::Timeout(Json(ErrorPayload::Error {
+            reason: format!("{}:\n  error: {}", error, e),
+        }))
+    } else {
+        ScrapeError::Error(Json(ErrorPayload::Error {
             reason: if let Some(status) = e.status() {
                 format!("{}:\n  status: {}\n  error: {}", error, status.as_u16(), e)
             } else {
                 format!("{}:\n  error: {}", error, e)
             },
-        }))),
+        }))
     }
 }
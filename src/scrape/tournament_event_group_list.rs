@@ -1,23 +1,20 @@
 use std::collections::HashMap;
-use std::time::Duration;
 
 use once_cell::sync::Lazy;
 use regex::Regex;
 use rocket::serde::Serialize;
 use scraper::{ElementRef, Html, Selector};
+use tracing::field::Empty;
+use tracing::instrument;
 
-use crate::client::Client;
-use crate::scrape::{
-    ScrapeCache, ScrapeResult, TOURNAMENT_EVENT_BRACKET_PAGE_REFRESH,
-    TOURNAMENT_EVENT_LIST_REFRESH, TOURNAMENT_EVENT_PLAYER_LIST_PAGES_REFRESH,
-    TOURNAMENT_PAGE_REFRESH,
-};
-use crate::util::cache::{CacheGuard, CacheMapGuard};
-use crate::util::guard_stack::GuardStack;
+use crate::client::{Client, Upstream};
+use crate::scrape::health::Check;
+use crate::scrape::{ParseWarningKind, ParseWarnings, ScrapeCache, ScrapeResult};
+use crate::util::cache::CacheSnapshot;
 
 pub type EventGroupList = Vec<EventGroup>;
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Hash, Serialize)]
 #[serde(crate = "rocket::serde")]
 #[serde(rename_all = "camelCase")]
 pub struct EventGroup {
@@ -25,7 +22,7 @@ pub struct EventGroup {
     pub events: Vec<Event>,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Hash, Serialize)]
 #[serde(crate = "rocket::serde")]
 #[serde(rename_all = "camelCase")]
 pub struct Event {
@@ -34,7 +31,7 @@ pub struct Event {
     pub url: EventUrl,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub enum EventUrl {
     Bracket(String),
     GroupList(String),
@@ -51,207 +48,283 @@ impl EventUrl {
     }
 }
 
-pub type EventGroupListGuard<'a> = GuardStack<
-    'a,
-    (
-        CacheMapGuard<'a, usize, EventGroupList>,
-        CacheGuard<'a, EventGroupList>,
-    ),
-    EventGroupList,
->;
-
+#[instrument(skip(client, cache), err, fields(cache = Empty, parse_ms = Empty, unchanged = Empty))]
 pub async fn tournament_event_group_list<'a>(
     tournament_id: usize,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
-) -> ScrapeResult<EventGroupListGuard<'a>> {
-    cache
-        .tournament_event_list
-        .get(tournament_id)
-        .await
-        .try_push_guard_async(|event_list_cache| async move {
-            event_list_cache
-                .retrieve_or_update(
-                    Duration::from_secs(TOURNAMENT_EVENT_LIST_REFRESH),
-                    || async {
-                        let tournament_page_url = format!(
-                            "https://www.pickleballtournaments.com/tournamentinfo.pl?tid={}",
-                            tournament_id
-                        );
-
-                        let event_bracket_page_url = format!(
-                            "https://www.pickleballtournaments.com/cinfo.pl?tid={}",
+) -> ScrapeResult<CacheSnapshot<EventGroupList>> {
+    let event_list_cache = cache.tournament_event_list.get(tournament_id);
+
+    event_list_cache
+        .retrieve_or_serve_stale(
+            cache.refresh_interval(tournament_id, cache.refresh.tournament_event_list),
+            || async {
+                let tournament_page_url = client
+                    .upstream()
+                    .url(&format!("tournamentinfo.pl?tid={}", tournament_id));
+
+                let event_bracket_page_url = client
+                    .upstream()
+                    .url(&format!("cinfo.pl?tid={}", tournament_id));
+
+                // First, try to get the event groups from the Events/Brackets page.
+                let fetched_event_bracket_page = cache
+                    .pages
+                    .retrieve_or_update(
+                        cache.refresh_interval(
                             tournament_id,
-                        );
-
-                        // First, try to get the event groups from the Events/Brackets page.
-                        let mut event_groups = {
-                            let event_bracket_page_raw_html = cache
-                                .pages
-                                .retrieve_or_update(
-                                    Duration::from_secs(TOURNAMENT_EVENT_BRACKET_PAGE_REFRESH),
-                                    &event_bracket_page_url,
-                                    |url| async {
-                                        client
-                                            .get(url)
-                                            .header("Referer", &tournament_page_url)
-                                            .header("Sec-Fetch-Site", "same-origin")
-                                            .send()
-                                            .await
-                                    },
-                                    "could not load event tournament bracket list",
-                                )
-                                .await?
-                                .clone();
-
-                            let event_bracket_page =
-                                Html::parse_document(&event_bracket_page_raw_html);
-
-                            event_bracket_page
-                                .select(&SELECTORS.section)
-                                .map(|n| {
-                                    let event_group_name = n.inner_html();
-
-                                    let mut url_to_event_name = HashMap::new();
-
-                                    n.next_siblings()
-                                        .filter_map(ElementRef::wrap)
-                                        .skip(1)
-                                        .take_while(|e| &e.value().name.local != "h2")
-                                        .for_each(|e| {
-                                            let event_element =
-                                                e.select(&SELECTORS.event).next().unwrap();
-                                            let event_name = event_element.inner_html();
-                                            let event_html = event_element.html();
-                                            let event_url =
-                                                PATTERNS.url.captures(&event_html).unwrap()[1]
-                                                    .to_owned();
-
-                                            url_to_event_name
-                                                .entry(event_url)
-                                                .or_insert_with(Vec::new)
-                                                .push(event_name);
-                                        });
-
-                                    EventGroup {
-                                        name: event_group_name,
-                                        events: url_to_event_name
-                                            .into_iter()
-                                            .map(|(url, names)| Event {
-                                                name: common_name(&names),
-                                                url: if url.contains("rptbrackets.pl") {
-                                                    EventUrl::List(format!(
-                                                        "https://www.pickleballtournaments.com/{}",
-                                                        url
-                                                    ))
-                                                } else if url.contains("show.pl") {
-                                                    let bracket_filename_captures = PATTERNS.bracket_filename.captures(&url).unwrap();
-                                                    EventUrl::Bracket(format!(
-                                                        "https://www.pickleballtournaments.com/Tournaments/{}/{}",
-                                                        bracket_filename_captures[1].replace("%2F", "/"),
-                                                        &bracket_filename_captures[2],
-                                                    ))
-                                                } else {
-                                                    panic!("Unknown event url: {:?}", url)
-                                                },
-                                            })
-                                            .collect(),
+                            cache.refresh.tournament_event_bracket_page,
+                        ),
+                        &event_bracket_page_url,
+                        |url| {
+                            client
+                                .get(url)
+                                .header("Referer", &tournament_page_url)
+                                .header("Sec-Fetch-Site", "same-origin")
+                        },
+                        "could not load event tournament bracket list",
+                    )
+                    .await?;
+                event_list_cache.depend_on(&fetched_event_bracket_page);
+
+                // The warnings from the Events/Brackets page are held back until we know which
+                // pages the event groups come from, so that they aren't reported twice.
+                let event_bracket_warnings = ParseWarnings::new(&event_bracket_page_url);
+                let event_groups = event_list_cache.derive(
+                    ("event_bracket_list", fetched_event_bracket_page.hash),
+                    || {
+                        Ok(parse_event_bracket_list(
+                            &fetched_event_bracket_page.body,
+                            client.upstream(),
+                            &event_bracket_warnings,
+                        ))
+                    },
+                )?;
+
+                if !event_groups.is_empty() {
+                    event_list_cache.warn(event_bracket_warnings.into_vec());
+                    return Ok(event_groups);
+                }
+
+                // If we don't get any event groups from that, scrape each of the Event Player List pages
+                let fetched_tournament_page = cache
+                    .pages
+                    .retrieve_or_update(
+                        cache.refresh_interval(tournament_id, cache.refresh.tournament_page),
+                        &tournament_page_url,
+                        |url| client.get(url),
+                        "could not load tournament info",
+                    )
+                    .await?;
+                event_list_cache.depend_on(&fetched_tournament_page);
+
+                let tournament_page_warnings = ParseWarnings::new(&tournament_page_url);
+                let event_list_urls = parse_event_list_urls(
+                    &fetched_tournament_page.body,
+                    client.upstream(),
+                    &tournament_page_warnings,
+                );
+
+                let mut event_pages = Vec::with_capacity(event_list_urls.len());
+                for (name, url) in event_list_urls.iter() {
+                    let event_page = cache
+                        .pages
+                        .retrieve_or_update(
+                            cache.refresh_interval(
+                                tournament_id,
+                                cache.refresh.tournament_event_player_list_pages,
+                            ),
+                            url,
+                            |url| {
+                                client
+                                    .get(url)
+                                    .header("Referer", &tournament_page_url)
+                                    .header("Sec-Fetch-Site", "same-origin")
+                            },
+                            &format!("could not load event player page for {:?}", name),
+                        )
+                        .await?;
+                    event_list_cache.depend_on(&event_page);
+                    event_pages.push(event_page);
+                }
+
+                event_list_cache.derive(
+                    (
+                        "event_player_lists",
+                        fetched_event_bracket_page.hash,
+                        fetched_tournament_page.hash,
+                        event_pages.iter().map(|p| p.hash).collect::<Vec<_>>(),
+                    ),
+                    || {
+                        event_list_cache.warn(event_bracket_warnings.into_vec());
+                        event_list_cache.warn(tournament_page_warnings.into_vec());
+
+                        Ok(event_list_urls
+                            .into_iter()
+                            .zip(event_pages.iter())
+                            .map(|((name, url), event_page)| {
+                                parse_event_player_list(name, url, &event_page.body)
+                            })
+                            .collect())
+                    },
+                )
+            },
+        )
+        .await
+}
+
+/// Reads the event groups from the Events/Brackets page, which has the events in each group.
+/// Events whose links can't be made sense of are left out.
+pub fn parse_event_bracket_list(
+    event_bracket_page_html: &str,
+    upstream: &Upstream,
+    warnings: &ParseWarnings,
+) -> EventGroupList {
+    let event_bracket_page = Html::parse_document(event_bracket_page_html);
+
+    event_bracket_page
+        .select(&SELECTORS.section)
+        .map(|n| {
+            let event_group_name = n.inner_html();
+
+            let mut url_to_event_name = HashMap::new();
+
+            n.next_siblings()
+                .filter_map(ElementRef::wrap)
+                .skip(1)
+                .take_while(|e| &e.value().name.local != "h2")
+                .for_each(|e| {
+                    let event_element = match e.select(&SELECTORS.event).next() {
+                        Some(event_element) => event_element,
+                        None => {
+                            warnings.warn(ParseWarningKind::MalformedEventList, e.html());
+                            return;
+                        }
+                    };
+                    let event_name = event_element.inner_html();
+                    let event_html = event_element.html();
+                    let event_url = match PATTERNS.url.captures(&event_html) {
+                        Some(captures) => captures[1].to_owned(),
+                        None => {
+                            warnings.warn(ParseWarningKind::MalformedEventList, event_html);
+                            return;
+                        }
+                    };
+
+                    url_to_event_name
+                        .entry(event_url)
+                        .or_insert_with(Vec::new)
+                        .push(event_name);
+                });
+
+            EventGroup {
+                name: event_group_name,
+                events: url_to_event_name
+                    .into_iter()
+                    .filter_map(|(url, names)| {
+                        let url = if url.contains("rptbrackets.pl") {
+                            EventUrl::List(upstream.url(&url))
+                        } else if url.contains("show.pl") {
+                            let bracket_filename_captures =
+                                match PATTERNS.bracket_filename.captures(&url) {
+                                    Some(captures) => captures,
+                                    None => {
+                                        warnings.warn(ParseWarningKind::MalformedEventList, url);
+                                        return None;
                                     }
-                                })
-                                .collect::<Vec<_>>()
+                                };
+                            EventUrl::Bracket(upstream.url(&format!(
+                                "Tournaments/{}/{}",
+                                bracket_filename_captures[1].replace("%2F", "/"),
+                                &bracket_filename_captures[2],
+                            )))
+                        } else {
+                            warnings.warn(ParseWarningKind::MalformedEventList, url);
+                            return None;
                         };
 
-                        // If we don't get any event groups from that, scrape each of the Event Player List pages
-                        if event_groups.is_empty() {
-                            let tournament_page_raw_html = cache
-                                .pages
-                                .retrieve_or_update(
-                                    Duration::from_secs(TOURNAMENT_PAGE_REFRESH),
-                                    &tournament_page_url,
-                                    |url| async { client.get(url).send().await },
-                                    "could not load tournament info",
-                                )
-                                .await?
-                                .clone();
-
-                            let event_list_urls = {
-                                let tournament_page =
-                                    Html::parse_document(&tournament_page_raw_html);
-
-                                tournament_page
-                                    .select(&SELECTORS.event_list)
-                                    .filter(|e| {
-                                        !sanitize_name(&e.inner_html()).contains("waitlist")
-                                    })
-                                    .map(|e| {
-                                        let html = e.html();
-                                        (
-                                            e.inner_html(),
-                                            format!(
-                                                "https://www.pickleballtournaments.com/{}",
-                                                &PATTERNS.url.captures(&html).unwrap()[1]
-                                            ),
-                                        )
-                                    })
-                                    .collect::<Vec<_>>()
-                            };
-
-                            let mut event_pages = Vec::with_capacity(event_list_urls.len());
-                            for (name, url) in event_list_urls.iter() {
-                                event_pages.push(
-                                    cache
-                                        .pages
-                                        .retrieve_or_update(
-                                            Duration::from_secs(
-                                                TOURNAMENT_EVENT_PLAYER_LIST_PAGES_REFRESH,
-                                            ),
-                                            url,
-                                            |url| async {
-                                                client
-                                                    .get(url)
-                                                    .header("Referer", &tournament_page_url)
-                                                    .header("Sec-Fetch-Site", "same-origin")
-                                                    .send()
-                                                    .await
-                                            },
-                                            &format!(
-                                                "could not load event player page for {:?}",
-                                                name
-                                            ),
-                                        )
-                                        .await?
-                                        .clone(),
-                                );
-                            }
-
-                            event_groups = event_list_urls
-                                .into_iter()
-                                .zip(event_pages.into_iter())
-                                .map(|((name, url), page_raw_html)| {
-                                    let page = Html::parse_document(&page_raw_html);
-
-                                    EventGroup {
-                                        name,
-                                        events: page
-                                            .select(&SELECTORS.section)
-                                            .map(|s| s.inner_html())
-                                            .filter(|s| !s.trim().is_empty())
-                                            .map(|s| Event {
-                                                name: s,
-                                                url: EventUrl::GroupList(url.clone()),
-                                            })
-                                            .collect(),
-                                    }
-                                })
-                                .collect();
-                        }
+                        Some(Event {
+                            name: common_name(&names),
+                            url,
+                        })
+                    })
+                    .collect(),
+            }
+        })
+        .collect::<Vec<_>>()
+}
 
-                        Ok(event_groups)
-                    },
-                )
-                .await
+/// Reads the names and URLs of the Event Player List pages linked from the tournament page
+pub fn parse_event_list_urls(
+    tournament_page_html: &str,
+    upstream: &Upstream,
+    warnings: &ParseWarnings,
+) -> Vec<(String, String)> {
+    let tournament_page = Html::parse_document(tournament_page_html);
+
+    tournament_page
+        .select(&SELECTORS.event_list)
+        .filter(|e| !sanitize_name(&e.inner_html()).contains("waitlist"))
+        .filter_map(|e| {
+            let html = e.html();
+            match PATTERNS.url.captures(&html) {
+                Some(captures) => Some((e.inner_html(), upstream.url(&captures[1]))),
+                None => {
+                    warnings.warn(ParseWarningKind::MalformedEventList, html);
+                    None
+                }
+            }
         })
-        .await
+        .collect::<Vec<_>>()
+}
+
+/// Reads the event group whose Event Player List page is at `url`, which lists its events
+pub fn parse_event_player_list(name: String, url: String, event_page_html: &str) -> EventGroup {
+    let page = Html::parse_document(event_page_html);
+
+    EventGroup {
+        name,
+        events: page
+            .select(&SELECTORS.section)
+            .map(|s| s.inner_html())
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| Event {
+                name: s,
+                url: EventUrl::GroupList(url.clone()),
+            })
+            .collect(),
+    }
+}
+
+/// What the health check looks for on the Events/Brackets page
+pub fn event_bracket_list_checks() -> Vec<Check> {
+    vec![
+        Check::selector("section", &SELECTORS.section),
+        Check::selector("event", &SELECTORS.event),
+        Check::pattern("url", &PATTERNS.url),
+        // Events that only have a player list don't link to a bracket file.
+        Check::pattern_in("bracket_filename", &PATTERNS.bracket_filename, |page| {
+            page.select(&SELECTORS.event)
+                .filter_map(|e| PATTERNS.url.captures(&e.html()).map(|c| c[1].to_owned()))
+                .collect()
+        })
+        .optional(),
+    ]
+}
+
+/// What the health check looks for on the tournament page.  These are only needed when the
+/// Events/Brackets page is empty.
+pub fn tournament_page_checks() -> Vec<Check> {
+    vec![
+        Check::selector("event_list", &SELECTORS.event_list).optional(),
+        Check::pattern("url", &PATTERNS.url),
+    ]
+}
+
+/// What the health check looks for on an Event Player List page
+pub fn event_player_list_checks() -> Vec<Check> {
+    vec![Check::selector("section", &SELECTORS.section)]
 }
 
 struct Selectors {
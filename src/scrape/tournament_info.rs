@@ -1,83 +1,106 @@
-use std::time::Duration;
-
+use once_cell::sync::Lazy;
 use regex::Regex;
 use scraper::{Html, Selector};
+use tracing::field::Empty;
+use tracing::instrument;
 
 use crate::client::Client;
-use crate::scrape::{ScrapeCache, ScrapeResult, TOURNAMENT_INFO_REFRESH, TOURNAMENT_PAGE_REFRESH};
-use crate::util::cache::{CacheGuard, CacheMapGuard};
-use crate::util::guard_stack::GuardStack;
+use crate::scrape::health::Check;
+use crate::scrape::{ScrapeCache, ScrapeResult};
+use crate::util::cache::CacheSnapshot;
 
 pub type Info = Vec<(String, String)>;
 
-pub type InfoGuard<'a> =
-    GuardStack<'a, (CacheMapGuard<'a, usize, Info>, CacheGuard<'a, Info>), Info>;
-
+#[instrument(skip(client, cache), err, fields(cache = Empty, parse_ms = Empty, unchanged = Empty))]
 pub async fn tournament_info<'a>(
     tournament_id: usize,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
-) -> ScrapeResult<InfoGuard<'a>> {
-    cache
-        .tournament_info
-        .get(tournament_id)
-        .await
-        .try_push_guard_async(|info_cache| async move {
-            info_cache
-                .retrieve_or_update(Duration::from_secs(TOURNAMENT_INFO_REFRESH), || async {
-                    let tournament_page_url = format!(
-                        "https://www.pickleballtournaments.com/tournamentinfo.pl?tid={}",
-                        tournament_id
-                    );
+) -> ScrapeResult<CacheSnapshot<Info>> {
+    let info_cache = cache.tournament_info.get(tournament_id);
 
-                    let tournament_page_raw_html = cache
-                        .pages
-                        .retrieve_or_update(
-                            Duration::from_secs(TOURNAMENT_PAGE_REFRESH),
-                            &tournament_page_url,
-                            |url| async { client.get(url).send().await },
-                            "could not load tournament info",
-                        )
-                        .await?
-                        .clone();
+    info_cache
+        .retrieve_or_serve_stale(
+            cache.refresh_interval(tournament_id, cache.refresh.tournament_info),
+            || async {
+                let tournament_page_url = client
+                    .upstream()
+                    .url(&format!("tournamentinfo.pl?tid={}", tournament_id));
 
-                    let tournament_page = Html::parse_document(&tournament_page_raw_html);
+                let tournament_page = cache
+                    .pages
+                    .retrieve_or_update(
+                        cache.refresh_interval(tournament_id, cache.refresh.tournament_page),
+                        &tournament_page_url,
+                        |url| client.get(url),
+                        "could not load tournament info",
+                    )
+                    .await?;
 
-                    let nav_item_selector =
-                        Selector::parse(".nav > .nav-item > .nav-link").unwrap();
+                info_cache.depend_on(&tournament_page);
+                info_cache.derive(tournament_page.hash, || {
+                    Ok(parse_info(&tournament_page.body))
+                })
+            },
+        )
+        .await
+}
 
-                    let href_pattern = Regex::new(r#"href="([^"]+)""#).unwrap();
+/// Reads the tabs of the tournament page that aren't covered elsewhere, as (tab name, contents) pairs
+pub fn parse_info(tournament_page_html: &str) -> Info {
+    let tournament_page = Html::parse_document(tournament_page_html);
 
-                    Ok(tournament_page
-                        .select(&nav_item_selector)
-                        .filter_map(|l| {
-                            let html = l.html();
-                            href_pattern
-                                .captures(&html)
-                                .map(|c| (c[1].to_owned(), l.inner_html()))
-                        })
-                        .filter(|(l, _)| l.starts_with('#'))
-                        .filter(|(l, _)| {
-                            [
-                                "#menuSchedule",
-                                "#menuPlayerList",
-                                "#menuEventList",
-                                "#menuPlayersNeedingPartners",
-                                "#menuFindPlayer",
-                            ]
-                            .iter()
-                            .all(|t| l != t)
-                        })
-                        .filter_map(|(l, n)| {
-                            let selector = Selector::parse(&l).unwrap();
-                            tournament_page
-                                .select(&selector)
-                                .next()
-                                .map(|e| (n, e.inner_html()))
-                        })
-                        .collect())
-                })
-                .await
+    tournament_page
+        .select(&SELECTORS.nav_item)
+        .filter_map(|l| {
+            let html = l.html();
+            PATTERNS
+                .href
+                .captures(&html)
+                .map(|c| (c[1].to_owned(), l.inner_html()))
         })
-        .await
+        .filter(|(l, _)| l.starts_with('#'))
+        .filter(|(l, _)| {
+            [
+                "#menuSchedule",
+                "#menuPlayerList",
+                "#menuEventList",
+                "#menuPlayersNeedingPartners",
+                "#menuFindPlayer",
+            ]
+            .iter()
+            .all(|t| l != t)
+        })
+        .filter_map(|(l, n)| {
+            let selector = Selector::parse(&l).unwrap();
+            tournament_page
+                .select(&selector)
+                .next()
+                .map(|e| (n, e.inner_html()))
+        })
+        .collect()
+}
+
+/// What the health check looks for on the tournament page
+pub fn checks() -> Vec<Check> {
+    vec![
+        Check::selector("nav_item", &SELECTORS.nav_item),
+        Check::pattern("href", &PATTERNS.href),
+    ]
+}
+
+struct Selectors {
+    nav_item: Selector,
 }
+
+static SELECTORS: Lazy<Selectors> = Lazy::new(|| Selectors {
+    nav_item: Selector::parse(".nav > .nav-item > .nav-link").unwrap(),
+});
+
+struct Patterns {
+    href: Regex,
+}
+
+static PATTERNS: Lazy<Patterns> = Lazy::new(|| Patterns {
+    href: Regex::new(r#"href="([^"]+)""#).unwrap(),
+});
@@ -1,14 +1,18 @@
 use std::time::Duration;
 
-use async_std::sync::RwLockReadGuard;
 use chrono::prelude::*;
 use once_cell::sync::Lazy;
-use regex::Regex;
+use regex::{Captures, Regex};
 use rocket::serde::Serialize;
 use scraper::{ElementRef, Html, Selector};
+use tracing::field::Empty;
+use tracing::instrument;
 
-use crate::client::Client;
-use crate::scrape::{ScrapeCache, ScrapeResult, TOURNAMENT_LIST_REFRESH};
+use crate::client::{Client, RequestClass, Upstream};
+use crate::scrape::health::Check;
+use crate::scrape::tournament_schedule::parse_time;
+use crate::scrape::{ParseWarningKind, ParseWarnings, ScrapeCache, ScrapeResult};
+use crate::util::cache::CacheSnapshot;
 
 pub type TournamentList = Vec<TournamentListing>;
 
@@ -18,9 +22,9 @@ pub type TournamentList = Vec<TournamentListing>;
 pub struct TournamentListing {
     pub id: usize,
     pub name: String,
-    pub location: String,
-    pub start_date: String,
-    pub end_date: String,
+    pub location: Option<String>,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
     pub tag_urls: Vec<String>,
     pub logo_url: Option<String>,
     pub registration_status: RegistrationStatus,
@@ -34,74 +38,139 @@ pub enum RegistrationStatus {
     Closed,
     #[serde(rename_all = "camelCase")]
     OpenSoon {
-        start_date: String,
-        start_time: String,
+        start_date: NaiveDate,
+        start_time: NaiveTime,
+        start_time_zone: Option<String>,
     },
     #[serde(rename_all = "camelCase")]
     Open {
-        deadline: String,
+        deadline: NaiveDate,
     },
     #[serde(rename_all = "camelCase")]
     ClosedToNew {
-        payment_deadline: String,
+        payment_deadline: NaiveDate,
     },
+    /// A status we don't know how to read, with its text as it appeared on the listing
+    #[serde(rename_all = "camelCase")]
+    Unknown {
+        raw: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TournamentPhase {
+    Upcoming,
+    Ongoing,
+    Past,
 }
 
-pub type TournamentListGuard<'a> = RwLockReadGuard<'a, TournamentList>;
+impl TournamentListing {
+    /// Where the tournament is in its lifecycle on `today`.  The day after a tournament still counts
+    /// as ongoing since results are often posted late.
+    pub fn phase(&self, today: NaiveDate) -> TournamentPhase {
+        if today < self.start_date {
+            TournamentPhase::Upcoming
+        } else if today > self.end_date.succ() {
+            TournamentPhase::Past
+        } else {
+            TournamentPhase::Ongoing
+        }
+    }
+}
 
+#[instrument(skip_all, err, fields(cache = Empty, parse_ms = Empty, unchanged = Empty))]
 pub async fn tournament_list<'a>(
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
-) -> ScrapeResult<TournamentListGuard<'a>> {
+) -> ScrapeResult<CacheSnapshot<TournamentList>> {
     cache
         .tournament_list
-        .retrieve_or_update(Duration::from_secs(TOURNAMENT_LIST_REFRESH), || async {
-            let future_raw_html = cache
-                .pages
-                .retrieve_or_update(
-                    Duration::from_secs(TOURNAMENT_LIST_REFRESH),
-                    "https://www.pickleballtournaments.com/pbt_tlisting.pl?when=F",
-                    |url| async { client.get(url).send().await },
-                    "could not load future tournaments",
-                )
-                .await?
-                .clone();
-
-            let past_raw_html = cache
-                .pages
-                .retrieve_or_update(
-                    Duration::from_secs(TOURNAMENT_LIST_REFRESH),
-                    "https://www.pickleballtournaments.com/pbt_tlisting.pl?when=P",
-                    |url| async {
-                        client
-                            .get(url)
-                            .header(
-                                "Referer",
-                                "https://www.pickleballtournaments.com/pbt_tlisting.pl?when=F",
-                            )
-                            .header("Sec-Fetch-Site", "same-origin")
-                            .send()
-                            .await
-                    },
-                    "could not load past tournaments",
-                )
-                .await?
-                .clone();
-
-            let future_document = Html::parse_document(&future_raw_html);
-            let past_document = Html::parse_document(&past_raw_html);
-
-            let tournament_listings = future_document
-                .select(&SELECTORS.tournament)
-                .chain(past_document.select(&SELECTORS.tournament))
-                .map(parse_tournament_listing)
-                .collect::<Vec<_>>();
-
-            Ok(tournament_listings)
-        })
+        .retrieve_or_serve_stale(
+            Duration::from_secs(cache.refresh.tournament_list),
+            || async {
+                let future_page_url = client.upstream().url("pbt_tlisting.pl?when=F");
+                let past_page_url = client.upstream().url("pbt_tlisting.pl?when=P");
+
+                let future_page = cache
+                    .pages
+                    .retrieve_or_update(
+                        Duration::from_secs(cache.refresh.tournament_list),
+                        &future_page_url,
+                        |url| client.get(url).class(RequestClass::Listing),
+                        "could not load future tournaments",
+                    )
+                    .await?;
+
+                let past_page = cache
+                    .pages
+                    .retrieve_or_update(
+                        Duration::from_secs(cache.refresh.tournament_list),
+                        &past_page_url,
+                        |url| {
+                            client
+                                .get(url)
+                                .class(RequestClass::Listing)
+                                .header("Referer", &future_page_url)
+                                .header("Sec-Fetch-Site", "same-origin")
+                        },
+                        "could not load past tournaments",
+                    )
+                    .await?;
+
+                cache.tournament_list.depend_on(&future_page);
+                cache.tournament_list.depend_on(&past_page);
+                cache
+                    .tournament_list
+                    .derive((future_page.hash, past_page.hash), || {
+                        let future_warnings = ParseWarnings::new(&future_page_url);
+                        let past_warnings = ParseWarnings::new(&past_page_url);
+
+                        let mut tournament_listings = parse_tournament_list(
+                            &future_page.body,
+                            client.upstream(),
+                            &future_warnings,
+                        );
+                        tournament_listings.extend(parse_tournament_list(
+                            &past_page.body,
+                            client.upstream(),
+                            &past_warnings,
+                        ));
+
+                        cache.tournament_list.warn(
+                            future_warnings
+                                .into_vec()
+                                .into_iter()
+                                .chain(past_warnings.into_vec()),
+                        );
+
+                        Ok(tournament_listings)
+                    })
+            },
+        )
         .await
 }
 
+/// What the health check looks for on a tournament list page
+pub fn checks() -> Vec<Check> {
+    vec![
+        Check::selector("tournament", &SELECTORS.tournament),
+        Check::selector("title", &SELECTORS.title),
+        Check::selector("location", &SELECTORS.location),
+        Check::selector("date", &SELECTORS.date),
+        // These depend on the tournaments that happen to be listed.
+        Check::selector("tag", &SELECTORS.tag).optional(),
+        Check::selector("logo", &SELECTORS.logo).optional(),
+        Check::selector("registration", &SELECTORS.registration).optional(),
+        Check::selector("is_adonly", &SELECTORS.is_adonly).optional(),
+        Check::selector("soon", &SELECTORS.soon).optional(),
+        Check::pattern("id", &PATTERNS.id),
+        Check::pattern("date", &PATTERNS.date),
+        Check::pattern("img_url", &PATTERNS.img_url).optional(),
+        Check::pattern("registration", &PATTERNS.registration).optional(),
+        Check::pattern("soon_time", &PATTERNS.soon_time).optional(),
+    ]
+}
+
 struct Selectors {
     tournament: Selector,
     title: Selector,
@@ -142,122 +211,124 @@ static PATTERNS: Lazy<Patterns> = Lazy::new(|| Patterns {
     soon_time: Regex::new(r"\d{1, 2}/\d{1, 2}/\d{2} (.+)").unwrap(),
 });
 
-fn parse_tournament_listing(tournament_element: ElementRef) -> TournamentListing {
-    let title_element = tournament_element.select(&SELECTORS.title).next().unwrap();
-    let title_element_html = title_element.html();
+/// Reads the tournaments on one of the tournament list pages.  Rows that can't be read are
+/// skipped or left incomplete, and noted in `warnings`.
+pub fn parse_tournament_list(
+    page_html: &str,
+    upstream: &Upstream,
+    warnings: &ParseWarnings,
+) -> TournamentList {
+    Html::parse_document(page_html)
+        .select(&SELECTORS.tournament)
+        .filter_map(|e| parse_tournament_listing(e, upstream, warnings))
+        .collect()
+}
+
+/// Parses one row of the tournament list, noting anything that's wrong with it in `warnings`.
+/// Rows without an id or a start date are skipped, while rows missing anything else are kept
+/// without it.  A row with only one date is taken to be a one day tournament.
+fn parse_tournament_listing(
+    tournament_element: ElementRef,
+    upstream: &Upstream,
+    warnings: &ParseWarnings,
+) -> Option<TournamentListing> {
+    let malformed = |raw: String| warnings.warn(ParseWarningKind::MalformedListing, raw);
 
-    let id = PATTERNS.id.captures(&title_element_html).unwrap()[1]
-        .parse::<usize>()
-        .unwrap();
+    let title_element = match tournament_element.select(&SELECTORS.title).next() {
+        Some(title_element) => title_element,
+        None => {
+            malformed(tournament_element.html());
+            return None;
+        }
+    };
+    let title_element_html = title_element.html();
     let name = title_element.inner_html();
 
+    let id = match PATTERNS
+        .id
+        .captures(&title_element_html)
+        .and_then(|c| c[1].parse::<usize>().ok())
+    {
+        Some(id) => id,
+        None => {
+            malformed(title_element_html);
+            return None;
+        }
+    };
+
     let location = tournament_element
         .select(&SELECTORS.location)
         .next()
-        .unwrap()
-        .inner_html();
-
-    let date_element = tournament_element.select(&SELECTORS.date).next().unwrap();
-    let date_element_inner_html = date_element.inner_html();
-    let mut dates_iter = PATTERNS.date.captures_iter(&date_element_inner_html);
-
-    let date_match = dates_iter.next().unwrap();
-    let start_date = NaiveDate::from_ymd(
-        date_match[3].parse::<i32>().unwrap() + 2000,
-        date_match[1].parse().unwrap(),
-        date_match[2].parse().unwrap(),
-    )
-    .format("%Y-%m-%d")
-    .to_string();
-
-    let date_match = dates_iter.next().unwrap();
-    let end_date = NaiveDate::from_ymd(
-        date_match[3].parse::<i32>().unwrap() + 2000,
-        date_match[1].parse().unwrap(),
-        date_match[2].parse().unwrap(),
-    )
-    .format("%Y-%m-%d")
-    .to_string();
+        .map(|e| e.inner_html());
+    if location.is_none() {
+        malformed(tournament_element.html());
+    }
+
+    let date_element_inner_html = tournament_element
+        .select(&SELECTORS.date)
+        .next()
+        .map(|e| e.inner_html())
+        .unwrap_or_default();
+    let mut dates = PATTERNS
+        .date
+        .captures_iter(&date_element_inner_html)
+        .map(|c| parse_date(&c));
+
+    let (start_date, end_date) = match (dates.next().flatten(), dates.next().flatten()) {
+        (Some(start_date), end_date) => (start_date, end_date.unwrap_or(start_date)),
+        (None, _) => {
+            malformed(tournament_element.html());
+            return None;
+        }
+    };
 
     let tag_urls = tournament_element
         .select(&SELECTORS.tag)
-        .map(|e| {
+        .filter_map(|e| {
             let inner_html = e.inner_html();
-            PATTERNS.img_url.captures(&inner_html).unwrap()[1].to_owned()
+            let tag_url = PATTERNS
+                .img_url
+                .captures(&inner_html)
+                .map(|c| c[1].to_owned());
+            if tag_url.is_none() {
+                malformed(e.html());
+            }
+            tag_url
         })
         .collect::<Vec<String>>();
 
-    let logo_url = {
-        let inner_html = tournament_element
-            .select(&SELECTORS.logo)
-            .next()
-            .unwrap()
-            .inner_html();
-        PATTERNS.img_url.captures(&inner_html).map(|c| {
-            if !c[1].starts_with("http") {
-                format!("https://www.pickleballtournaments.com{}", &c[1])
-            } else {
-                c[1].to_owned()
-            }
-        })
-    };
+    let logo_url = tournament_element
+        .select(&SELECTORS.logo)
+        .next()
+        .and_then(|e| {
+            let inner_html = e.inner_html();
+            PATTERNS.img_url.captures(&inner_html).map(|c| {
+                if !c[1].starts_with("http") {
+                    upstream.url(&c[1])
+                } else {
+                    c[1].to_owned()
+                }
+            })
+        });
 
     let registration_status = match tournament_element.select(&SELECTORS.registration).next() {
-        Some(registration_element) => {
-            let registration_html = registration_element.html();
-            match PATTERNS.registration.captures(&registration_html) {
-                Some(c) => match &c[1] {
-                    "closednow" => match registration_element.select(&SELECTORS.is_adonly).next() {
-                        Some(_) => RegistrationStatus::NotOpen,
-                        None => RegistrationStatus::Closed,
-                    },
-                    "closedpayonlynow" => {
-                        let date_match = PATTERNS.date.captures(&registration_html).unwrap();
-                        RegistrationStatus::ClosedToNew {
-                            payment_deadline: NaiveDate::from_ymd(
-                                date_match[3].parse::<i32>().unwrap() + 2000,
-                                date_match[1].parse().unwrap(),
-                                date_match[2].parse().unwrap(),
-                            )
-                            .format("%Y-%m-%d")
-                            .to_string(),
-                        }
-                    }
-                    "opennow" => {
-                        let date_match = PATTERNS.date.captures(&registration_html).unwrap();
-                        RegistrationStatus::Open {
-                            deadline: NaiveDate::from_ymd(
-                                date_match[3].parse::<i32>().unwrap() + 2000,
-                                date_match[1].parse().unwrap(),
-                                date_match[2].parse().unwrap(),
-                            )
-                            .format("%Y-%m-%d")
-                            .to_string(),
-                        }
-                    }
-                    other => panic!("unknown registration status: {}", other),
-                },
-                None => {
-                    let soon_element = registration_element.select(&SELECTORS.soon).next().unwrap();
-                    let inner_html = soon_element.inner_html();
-                    let date_match = PATTERNS.date.captures(&inner_html).unwrap();
-                    RegistrationStatus::OpenSoon {
-                        start_date: NaiveDate::from_ymd(
-                            date_match[3].parse::<i32>().unwrap() + 2000,
-                            date_match[1].parse().unwrap(),
-                            date_match[2].parse().unwrap(),
-                        )
-                        .format("%Y-%m-%d")
-                        .to_string(),
-                        start_time: PATTERNS.soon_time.captures(&inner_html).unwrap()[1].to_owned(),
-                    }
-                }
-            }
-        }
+        Some(registration_element) => parse_registration_status(registration_element)
+            .unwrap_or_else(|| {
+                let raw = registration_element
+                    .text()
+                    .flat_map(str::split_whitespace)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                warnings.warn(
+                    ParseWarningKind::UnknownRegistrationStatus,
+                    registration_element.html(),
+                );
+                RegistrationStatus::Unknown { raw }
+            }),
         None => RegistrationStatus::Closed,
     };
 
-    TournamentListing {
+    Some(TournamentListing {
         id,
         name,
         location,
@@ -266,5 +337,45 @@ fn parse_tournament_listing(tournament_element: ElementRef) -> TournamentListing
         tag_urls,
         logo_url,
         registration_status,
+    })
+}
+
+fn parse_registration_status(registration_element: ElementRef) -> Option<RegistrationStatus> {
+    let registration_html = registration_element.html();
+
+    match PATTERNS.registration.captures(&registration_html) {
+        Some(c) => match &c[1] {
+            "closednow" => match registration_element.select(&SELECTORS.is_adonly).next() {
+                Some(_) => Some(RegistrationStatus::NotOpen),
+                None => Some(RegistrationStatus::Closed),
+            },
+            "closedpayonlynow" => Some(RegistrationStatus::ClosedToNew {
+                payment_deadline: parse_date(&PATTERNS.date.captures(&registration_html)?)?,
+            }),
+            "opennow" => Some(RegistrationStatus::Open {
+                deadline: parse_date(&PATTERNS.date.captures(&registration_html)?)?,
+            }),
+            _ => None,
+        },
+        None => {
+            let soon_element = registration_element.select(&SELECTORS.soon).next()?;
+            let inner_html = soon_element.inner_html();
+            let (start_time, start_time_zone) =
+                parse_time(&PATTERNS.soon_time.captures(&inner_html)?[1])?;
+            Some(RegistrationStatus::OpenSoon {
+                start_date: parse_date(&PATTERNS.date.captures(&inner_html)?)?,
+                start_time,
+                start_time_zone,
+            })
+        }
     }
 }
+
+/// Reads a date matched by `PATTERNS.date`, if it's a real date
+fn parse_date(date_match: &Captures) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(
+        date_match[3].parse::<i32>().ok()? + 2000,
+        date_match[1].parse().ok()?,
+        date_match[2].parse().ok()?,
+    )
+}
@@ -1,20 +1,18 @@
-use std::time::Duration;
-
 use once_cell::sync::Lazy;
 use regex::Regex;
 use rocket::serde::Serialize;
 use scraper::{Html, Selector};
+use tracing::field::Empty;
+use tracing::instrument;
 
 use crate::client::Client;
-use crate::scrape::{
-    ScrapeCache, ScrapeResult, TOURNAMENT_PAGE_REFRESH, TOURNAMENT_PLAYER_LIST_REFRESH,
-};
-use crate::util::cache::{CacheGuard, CacheMapGuard};
-use crate::util::guard_stack::GuardStack;
+use crate::scrape::health::Check;
+use crate::scrape::{ScrapeCache, ScrapeResult};
+use crate::util::cache::CacheSnapshot;
 
 pub type PlayerList = Vec<Player>;
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, Hash, PartialEq, Serialize)]
 #[serde(crate = "rocket::serde")]
 #[serde(rename_all = "camelCase")]
 pub struct Player {
@@ -25,80 +23,81 @@ pub struct Player {
     pub from: String,
 }
 
-pub type PlayerListGuard<'a> = GuardStack<
-    'a,
-    (
-        CacheMapGuard<'a, usize, PlayerList>,
-        CacheGuard<'a, PlayerList>,
-    ),
-    PlayerList,
->;
-
+#[instrument(skip(client, cache), err, fields(cache = Empty, parse_ms = Empty, unchanged = Empty))]
 pub async fn tournament_player_list<'a>(
     tournament_id: usize,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
-) -> ScrapeResult<PlayerListGuard<'a>> {
-    cache
-        .tournament_player_list
-        .get(tournament_id)
+) -> ScrapeResult<CacheSnapshot<PlayerList>> {
+    let player_list_cache = cache.tournament_player_list.get(tournament_id);
+
+    player_list_cache
+        .retrieve_or_serve_stale(
+            cache.refresh_interval(tournament_id, cache.refresh.tournament_player_list),
+            || async {
+                let tournament_page_url = client
+                    .upstream()
+                    .url(&format!("tournamentinfo.pl?tid={}", tournament_id));
+
+                let tournament_page = cache
+                    .pages
+                    .retrieve_or_update(
+                        cache.refresh_interval(tournament_id, cache.refresh.tournament_page),
+                        &tournament_page_url,
+                        |url| client.get(url),
+                        "could not load tournament info",
+                    )
+                    .await?;
+
+                player_list_cache.depend_on(&tournament_page);
+                player_list_cache.derive(tournament_page.hash, || {
+                    Ok(parse_player_list(&tournament_page.body))
+                })
+            },
+        )
         .await
-        .try_push_guard_async(|player_list_cache| async move {
-            player_list_cache
-                .retrieve_or_update(
-                    Duration::from_secs(TOURNAMENT_PLAYER_LIST_REFRESH),
-                    || async {
-                        let tournament_page_url = format!(
-                            "https://www.pickleballtournaments.com/tournamentinfo.pl?tid={}",
-                            tournament_id
-                        );
-
-                        let tournament_page_raw_html = cache
-                            .pages
-                            .retrieve_or_update(
-                                Duration::from_secs(TOURNAMENT_PAGE_REFRESH),
-                                &tournament_page_url,
-                                |url| async { client.get(url).send().await },
-                                "could not load tournament info",
-                            )
-                            .await?
-                            .clone();
-
-                        let tournament_page_html = Html::parse_document(&tournament_page_raw_html);
-
-                        Ok(tournament_page_html
-                            .select(&SELECTORS.player)
-                            .map(|player_row| {
-                                let name_element =
-                                    player_row.select(&SELECTORS.player_name).next().unwrap();
-                                let name_html = name_element.html();
-                                let id = PATTERNS.player_id.captures(&name_html).unwrap()[1]
-                                    .parse()
-                                    .unwrap();
-                                let name_matches =
-                                    PATTERNS.player_name.captures(&name_html).unwrap();
-
-                                let from_element =
-                                    player_row.select(&SELECTORS.player_from).next().unwrap();
-
-                                Player {
-                                    id,
-                                    first_name: name_matches[2].trim().to_owned(),
-                                    last_name: name_matches[1].to_owned(),
-                                    nick_names: PATTERNS
-                                        .player_nick_name
-                                        .captures_iter(&name_html)
-                                        .map(|c| c[1].to_owned())
-                                        .collect(),
-                                    from: from_element.inner_html(),
-                                }
-                            })
-                            .collect::<Vec<_>>())
-                    },
-                )
-                .await
+}
+
+/// Reads the players registered for a tournament from the tournament page
+pub fn parse_player_list(tournament_page_html: &str) -> PlayerList {
+    Html::parse_document(tournament_page_html)
+        .select(&SELECTORS.player)
+        .map(|player_row| {
+            let name_element = player_row.select(&SELECTORS.player_name).next().unwrap();
+            let name_html = name_element.html();
+            let id = PATTERNS.player_id.captures(&name_html).unwrap()[1]
+                .parse()
+                .unwrap();
+            let name_matches = PATTERNS.player_name.captures(&name_html).unwrap();
+
+            let from_element = player_row.select(&SELECTORS.player_from).next().unwrap();
+
+            Player {
+                id,
+                first_name: name_matches[2].trim().to_owned(),
+                last_name: name_matches[1].to_owned(),
+                nick_names: PATTERNS
+                    .player_nick_name
+                    .captures_iter(&name_html)
+                    .map(|c| c[1].to_owned())
+                    .collect(),
+                from: from_element.inner_html(),
+            }
         })
-        .await
+        .collect::<Vec<_>>()
+}
+
+/// What the health check looks for on the tournament page
+pub fn checks() -> Vec<Check> {
+    vec![
+        Check::selector("player", &SELECTORS.player),
+        Check::selector("player_name", &SELECTORS.player_name),
+        Check::selector("player_from", &SELECTORS.player_from),
+        Check::pattern("player_id", &PATTERNS.player_id),
+        Check::pattern("player_name", &PATTERNS.player_name),
+        // Only players with nicknames have these.
+        Check::pattern("player_nick_name", &PATTERNS.player_nick_name).optional(),
+    ]
 }
 
 struct Selectors {
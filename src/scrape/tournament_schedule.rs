@@ -1,18 +1,16 @@
-use std::time::Duration;
-
 use chrono::prelude::*;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use rocket::serde::Serialize;
 use scraper::{Html, Selector};
+use tracing::field::Empty;
+use tracing::instrument;
 
-use crate::client::Client;
-use crate::scrape::tournament_event_group_list::tournament_event_group_list;
-use crate::scrape::{
-    ScrapeCache, ScrapeResult, TOURNAMENT_PAGE_REFRESH, TOURNAMENT_SCHEDULE_REFRESH,
-};
-use crate::util::cache::{CacheGuard, CacheMapGuard};
-use crate::util::guard_stack::GuardStack;
+use crate::client::{Client, Upstream};
+use crate::scrape::health::Check;
+use crate::scrape::tournament_event_group_list::{tournament_event_group_list, EventGroup};
+use crate::scrape::{ParseWarningKind, ParseWarnings, ScrapeCache, ScrapeResult};
+use crate::util::cache::CacheSnapshot;
 
 pub type Schedule = Vec<ScheduleItem>;
 
@@ -20,134 +18,194 @@ pub type Schedule = Vec<ScheduleItem>;
 #[serde(crate = "rocket::serde")]
 #[serde(rename_all = "camelCase")]
 pub struct ScheduleItem {
-    pub date: String,
-    pub time: String,
+    pub date: NaiveDate,
+    /// The time the item starts, unless it's something like "TBD" or "After finals"
+    pub time: Option<NaiveTime>,
+    /// The time zone written after the time, if any, as it appeared on the page
+    pub time_zone: Option<String>,
+    /// The time as it appeared on the page
+    pub raw_time: String,
     pub venue: String,
     pub event: String,
     pub link: Option<String>,
 }
 
-pub type ScheduleGuard<'a> =
-    GuardStack<'a, (CacheMapGuard<'a, usize, Schedule>, CacheGuard<'a, Schedule>), Schedule>;
-
+#[instrument(skip(client, cache), err, fields(cache = Empty, parse_ms = Empty, unchanged = Empty))]
 pub async fn tournament_schedule<'a>(
     tournament_id: usize,
     client: &'a Client<'a>,
     cache: &'a ScrapeCache,
-) -> ScrapeResult<ScheduleGuard<'a>> {
-    cache
-        .tournament_schedule
-        .get(tournament_id)
-        .await
-        .try_push_guard_async(|schedule_cache| async move {
-            schedule_cache
-                .retrieve_or_update(Duration::from_secs(TOURNAMENT_SCHEDULE_REFRESH), || async {
-                    let event_groups =
-                        tournament_event_group_list(tournament_id, client, cache).await?;
-
-                    let tournament_page_url = format!(
-                        "https://www.pickleballtournaments.com/tournamentinfo.pl?tid={}",
-                        tournament_id
-                    );
+) -> ScrapeResult<CacheSnapshot<Schedule>> {
+    let schedule_cache = cache.tournament_schedule.get(tournament_id);
 
-                    let tournament_page_raw_html = cache
-                        .pages
-                        .retrieve_or_update(
-                            Duration::from_secs(TOURNAMENT_PAGE_REFRESH),
-                            &tournament_page_url,
-                            |url| async { client.get(url).send().await },
-                            "could not load tournament info",
-                        )
-                        .await?
-                        .clone();
-
-                    let tournament_page = Html::parse_document(&tournament_page_raw_html);
-
-                    let mut schedule = Vec::new();
-
-                    for day_element in tournament_page.select(&SELECTORS.day) {
-                        let mut headers = day_element.select(&SELECTORS.header);
-
-                        let date_header = headers.next().unwrap().inner_html();
-                        let date_match = PATTERNS.date.captures(&date_header).unwrap();
-                        let date = NaiveDate::from_ymd(
-                            date_match[3].parse::<i32>().unwrap() + 2000,
-                            date_match[1].parse().unwrap(),
-                            date_match[2].parse().unwrap(),
-                        )
-                        .format("%Y-%m-%d")
-                        .to_string();
-
-                        let venues = {
-                            let mut values = headers.map(|h| h.inner_html()).collect::<Vec<_>>();
-                            values.pop(); // The last one isn't a venue.
-                            values
-                        };
+    schedule_cache
+        .retrieve_or_serve_stale(
+            cache.refresh_interval(tournament_id, cache.refresh.tournament_schedule),
+            || async {
+                let event_groups =
+                    tournament_event_group_list(tournament_id, client, cache).await?;
 
-                        for row in day_element.select(&SELECTORS.row).skip(2) {
-                            // Sometimes there are blank rows at the end of a day?
-                            let time =
-                                if let Some(time_element) = row.select(&SELECTORS.time).next() {
-                                    time_element.inner_html()
-                                } else {
-                                    continue;
-                                };
-
-                            for (venue, events_block) in
-                                venues.iter().zip(row.select(&SELECTORS.events))
-                            {
-                                let event_list = events_block.inner_html();
-
-                                // They bold schedule items that are bad, like wait lists.
-                                if event_list.starts_with("<b>") {
-                                    continue;
-                                }
-
-                                event_list
-                                    .split("<br>")
-                                    .filter(|e| e != &"&nbsp;")
-                                    .for_each(|e| {
-                                        let url = PATTERNS.url.captures(e).map(|c| {
-                                            format!(
-                                                "https://www.pickleballtournaments.com/{}",
-                                                &c[1]
-                                            )
-                                        });
-
-                                        let name = PATTERNS.name.captures(e).unwrap()[2].to_owned();
-
-                                        schedule.push(ScheduleItem {
-                                            date: date.clone(),
-                                            time: time.clone(),
-                                            venue: venue.clone(),
-                                            link: event_groups
-                                                .iter()
-                                                .flat_map(|g| g.events.iter())
-                                                .find(|e| e.name == name)
-                                                .map(|e| e.name.clone())
-                                                .or_else(|| {
-                                                    url.and_then(|url| {
-                                                        event_groups
-                                                            .iter()
-                                                            .flat_map(|g| g.events.iter())
-                                                            .find(|e| e.url.as_str() == url)
-                                                            .map(|e| e.name.clone())
-                                                    })
-                                                }),
-                                            event: name,
-                                        })
-                                    });
-                            }
-                        }
-                    }
+                let tournament_page_url = client
+                    .upstream()
+                    .url(&format!("tournamentinfo.pl?tid={}", tournament_id));
+
+                let tournament_page = cache
+                    .pages
+                    .retrieve_or_update(
+                        cache.refresh_interval(tournament_id, cache.refresh.tournament_page),
+                        &tournament_page_url,
+                        |url| client.get(url),
+                        "could not load tournament info",
+                    )
+                    .await?;
 
+                schedule_cache.depend_on(&event_groups);
+                schedule_cache.depend_on(&tournament_page);
+                schedule_cache.derive((&*event_groups, tournament_page.hash), || {
+                    let warnings = ParseWarnings::new(&tournament_page_url);
+                    let schedule = parse_schedule(
+                        &tournament_page.body,
+                        &event_groups,
+                        client.upstream(),
+                        &warnings,
+                    );
+
+                    schedule_cache.warn(warnings.into_vec());
                     Ok(schedule)
                 })
-                .await
-        })
+            },
+        )
         .await
 }
 
+/// Reads the schedule from the tournament page, linking each item to its event in `event_groups`
+pub fn parse_schedule(
+    tournament_page_html: &str,
+    event_groups: &[EventGroup],
+    upstream: &Upstream,
+    warnings: &ParseWarnings,
+) -> Schedule {
+    let tournament_page = Html::parse_document(tournament_page_html);
+
+    let mut schedule = Vec::new();
+
+    for day_element in tournament_page.select(&SELECTORS.day) {
+        let mut headers = day_element.select(&SELECTORS.header);
+
+        let date = match headers.next().and_then(|h| parse_date(&h.inner_html())) {
+            Some(date) => date,
+            None => {
+                warnings.warn(ParseWarningKind::MalformedSchedule, day_element.html());
+                continue;
+            }
+        };
+
+        let venues = {
+            let mut values = headers.map(|h| h.inner_html()).collect::<Vec<_>>();
+            values.pop(); // The last one isn't a venue.
+            values
+        };
+
+        for row in day_element.select(&SELECTORS.row).skip(2) {
+            // Sometimes there are blank rows at the end of a day?
+            let time_element = if let Some(time_element) = row.select(&SELECTORS.time).next() {
+                time_element
+            } else {
+                continue;
+            };
+
+            let raw_time = time_element
+                .text()
+                .flat_map(str::split_whitespace)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let (time, time_zone) = match parse_time(&time_element.inner_html()) {
+                Some((time, time_zone)) => (Some(time), time_zone),
+                None => (None, None),
+            };
+
+            for (venue, events_block) in venues.iter().zip(row.select(&SELECTORS.events)) {
+                let event_list = events_block.inner_html();
+
+                // They bold schedule items that are bad, like wait lists.
+                if event_list.starts_with("<b>") {
+                    continue;
+                }
+
+                event_list
+                    .split("<br>")
+                    .filter(|e| e != &"&nbsp;")
+                    .for_each(|e| {
+                        let url = PATTERNS.url.captures(e).map(|c| upstream.url(&c[1]));
+
+                        let name = match PATTERNS.name.captures(e) {
+                            Some(c) => c[2].to_owned(),
+                            None => {
+                                warnings.warn(ParseWarningKind::MalformedSchedule, e);
+                                return;
+                            }
+                        };
+
+                        schedule.push(ScheduleItem {
+                            date,
+                            time,
+                            time_zone: time_zone.clone(),
+                            raw_time: raw_time.clone(),
+                            venue: venue.clone(),
+                            link: event_groups
+                                .iter()
+                                .flat_map(|g| g.events.iter())
+                                .find(|e| e.name == name)
+                                .map(|e| e.name.clone())
+                                .or_else(|| {
+                                    url.and_then(|url| {
+                                        event_groups
+                                            .iter()
+                                            .flat_map(|g| g.events.iter())
+                                            .find(|e| e.url.as_str() == url)
+                                            .map(|e| e.name.clone())
+                                    })
+                                }),
+                            event: name,
+                        })
+                    });
+            }
+        }
+    }
+
+    // The page lists each day's items by time already, but that's nothing to rely on.  The sort
+    // is stable, so items at the same time keep the order of their venues, and items without a
+    // time go at the end of their day in the order they were listed.
+    schedule.sort_by_key(|i| (i.date, i.time.is_none(), i.time));
+
+    schedule
+}
+
+/// What the health check looks for on the tournament page
+pub fn checks() -> Vec<Check> {
+    vec![
+        Check::selector("day", &SELECTORS.day),
+        Check::selector("row", &SELECTORS.row),
+        Check::selector("header", &SELECTORS.header),
+        Check::selector("time", &SELECTORS.time),
+        Check::selector("events", &SELECTORS.events),
+        // Not every schedule item links to its event.
+        Check::pattern("url", &PATTERNS.url).optional(),
+        Check::pattern_in("name", &PATTERNS.name, |page| {
+            page.select(&SELECTORS.events)
+                .flat_map(|e| {
+                    e.inner_html()
+                        .split("<br>")
+                        .map(str::to_owned)
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        }),
+        Check::pattern("date", &PATTERNS.date),
+        Check::pattern("time", &PATTERNS.time),
+    ]
+}
+
 struct Selectors {
     day: Selector,
     row: Selector,
@@ -168,10 +226,46 @@ struct Patterns {
     url: Regex,
     name: Regex,
     date: Regex,
+    time: Regex,
 }
 
 static PATTERNS: Lazy<Patterns> = Lazy::new(|| Patterns {
     url: Regex::new(r#"href="([^"]+)""#).unwrap(),
     name: Regex::new(r"^(:?<a[^>]+>)?([^<]+)(:?</a>)?$").unwrap(),
     date: Regex::new(r"(\d{1, 2})/(\d{1, 2})/(\d{2})").unwrap(),
+    time: Regex::new(r"(\d{1, 2})(?::(\d{2}))? ?([AaPp])\.?[Mm]\.?(?:\s+([A-Z]{1, 5})\b)?")
+        .unwrap(),
 });
+
+/// Reads the date in a day's header, if it's a real date
+fn parse_date(date_header: &str) -> Option<NaiveDate> {
+    let date_match = PATTERNS.date.captures(date_header)?;
+
+    NaiveDate::from_ymd_opt(
+        date_match[3].parse::<i32>().ok()? + 2000,
+        date_match[1].parse().ok()?,
+        date_match[2].parse().ok()?,
+    )
+}
+
+/// Reads a 12-hour time like "8:00 AM" or "8pm", along with the time zone written after it, as in
+/// "10:00 AM EST"
+pub(super) fn parse_time(text: &str) -> Option<(NaiveTime, Option<String>)> {
+    let time_match = PATTERNS.time.captures(text)?;
+
+    let hour = match (time_match[1].parse::<u32>().ok()?, &time_match[3]) {
+        (0, _) | (13..=u32::MAX, _) => return None,
+        (12, "A" | "a") => 0,
+        (hour, "A" | "a") => hour,
+        (12, _) => 12,
+        (hour, _) => hour + 12,
+    };
+    let minute = match time_match.get(2) {
+        Some(minute) => minute.as_str().parse().ok()?,
+        None => 0,
+    };
+
+    let time_zone = time_match.get(4).map(|z| z.as_str().to_owned());
+
+    Some((NaiveTime::from_hms_opt(hour, minute, 0)?, time_zone))
+}
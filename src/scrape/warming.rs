@@ -0,0 +1,110 @@
+//! Periodically refreshes the caches for tournaments that are being played today, so that
+//! visitors don't have to wait on pickleballtournaments.com.
+
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+
+use crate::client::Client;
+use crate::scrape::event::{event_bracket, event_team_list};
+use crate::scrape::tournament_event_group_list::{tournament_event_group_list, EventUrl};
+use crate::scrape::tournament_list::{tournament_list, TournamentPhase};
+use crate::scrape::tournament_schedule::tournament_schedule;
+use crate::scrape::{ScrapeCache, ScrapeConfig, ScrapeError, ScrapeResult};
+
+/// Starts warming the caches once Rocket has launched
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Cache Warming", |rocket| {
+        Box::pin(async move {
+            let interval = match rocket.figment().extract::<ScrapeConfig>() {
+                Ok(config) if config.warm_interval() > 0 => {
+                    Duration::from_secs(config.warm_interval())
+                }
+                _ => return,
+            };
+
+            let cache = match rocket.state::<ScrapeCache>() {
+                Some(cache) => cache.clone(),
+                None => {
+                    error!("cache warming needs the scrape cache to be managed");
+                    return;
+                }
+            };
+
+            let client = match rocket.state::<Client<'static>>() {
+                Some(client) => client.clone(),
+                None => {
+                    error!("cache warming needs the upstream client to be managed");
+                    return;
+                }
+            };
+
+            let shutdown = rocket.shutdown();
+
+            rocket::tokio::spawn(async move {
+                rocket::tokio::select! {
+                    _ = warm_periodically(interval, client, cache) => {},
+                    _ = shutdown => {},
+                }
+            });
+        })
+    })
+}
+
+async fn warm_periodically(interval: Duration, client: Client<'static>, cache: ScrapeCache) {
+    loop {
+        if let Err(error) = warm(&client, &cache).await {
+            warn!("cache warming stopped early: {}", error);
+        }
+
+        async_std::task::sleep(interval).await;
+    }
+}
+
+async fn warm(client: &Client<'_>, cache: &ScrapeCache) -> ScrapeResult<()> {
+    let today = cache.today();
+
+    let ongoing_tournaments = tournament_list(client, cache)
+        .await?
+        .iter()
+        .filter(|t| t.phase(today) == TournamentPhase::Ongoing)
+        .map(|t| t.id)
+        .collect::<Vec<_>>();
+
+    for tournament_id in ongoing_tournaments {
+        // Individual failures shouldn't keep the rest of the tournaments from being warmed,
+        // but there's no use in continuing if we've been captcha'd.
+        match warm_tournament(tournament_id, client, cache).await {
+            Err(error @ ScrapeError::Captcha(_)) => return Err(error),
+            Err(error) => warn!("could not warm tournament {}: {}", tournament_id, error),
+            Ok(()) => (),
+        }
+    }
+
+    Ok(())
+}
+
+async fn warm_tournament(
+    tournament_id: usize,
+    client: &Client<'_>,
+    cache: &ScrapeCache,
+) -> ScrapeResult<()> {
+    tournament_schedule(tournament_id, client, cache).await?;
+
+    let events = tournament_event_group_list(tournament_id, client, cache)
+        .await?
+        .iter()
+        .flat_map(|g| g.events.iter())
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for event in events {
+        event_team_list(tournament_id, &event, client, cache).await?;
+
+        if let EventUrl::Bracket(_) = event.url {
+            event_bracket(tournament_id, &event, client, cache).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::cell::RefCell;
+
+use rocket::serde::Serialize;
+
+/// Something on a page that couldn't be made sense of, and was left out of what was parsed from it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct ParseWarning {
+    pub kind: ParseWarningKind,
+    /// The page it was on
+    pub url: String,
+    /// The part of the page that was left out
+    pub raw: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub enum ParseWarningKind {
+    /// A tournament listing that was skipped, or is missing something
+    MalformedListing,
+    UnknownRegistrationStatus,
+    /// A name that isn't on the tournament's player list
+    UnmatchedPlayer,
+    /// A day or item of the schedule that was skipped
+    MalformedSchedule,
+    /// An event or event list link that was skipped
+    MalformedEventList,
+}
+
+/// Collects the warnings for a page while it's being parsed
+pub struct ParseWarnings {
+    url: String,
+    warnings: RefCell<Vec<ParseWarning>>,
+}
+
+impl ParseWarnings {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn warn(&self, kind: ParseWarningKind, raw: impl Into<String>) {
+        let raw = raw.into();
+        warn!("{:?} on {}: {}", kind, self.url, raw);
+
+        self.warnings.borrow_mut().push(ParseWarning {
+            kind,
+            url: self.url.clone(),
+            raw,
+        });
+    }
+
+    /// Throws away everything collected so far, for when a page is parsed again a different way
+    pub fn clear(&self) {
+        self.warnings.borrow_mut().clear();
+    }
+
+    pub fn into_vec(self) -> Vec<ParseWarning> {
+        self.warnings.into_inner()
+    }
+}
@@ -1,143 +1,1198 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::future::Future;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::mem;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{
+    Arc, Mutex as SyncMutex, MutexGuard as SyncMutexGuard, RwLock as SyncRwLock, Weak,
+};
 use std::time::{Duration, Instant};
 
-use async_std::sync::{Mutex, RwLock, RwLockReadGuard};
-use reqwest::{Error, IntoUrl, Response, Url};
+use async_std::sync::Mutex;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{IntoUrl, StatusCode, Url};
+use rocket::serde::json::serde_json;
+use rocket::serde::{Deserialize, Serialize};
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
 
-use crate::scrape::{scrape_result, ScrapeResult};
-use crate::util::guard_stack::GuardStack;
+use crate::client::RequestBuilder;
+use crate::scrape::{scrape_body, scrape_result, ParseWarning, ScrapeResult};
+use crate::util::clock::{SharedClock, SystemClock};
+use crate::util::page_store::PageStore;
 
-pub type CacheGuard<'a, T> = RwLockReadGuard<'a, T>;
+/// A cached value as it was when it was retrieved, which knows whether it could be refreshed.
+/// Holding onto one doesn't keep the value from being refreshed.
+pub struct CacheSnapshot<T> {
+    value: Arc<T>,
+    freshness: Freshness,
+    warnings: Arc<Vec<ParseWarning>>,
+    origin: Origin,
+}
 
-/// Retrieves the stored value unless the storage interval has expired in which case the value is updated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Freshness {
+    Fresh,
+    /// Past its refresh interval, and being refreshed in the background
+    Revalidating,
+    /// Past its refresh interval, because the last refresh failed
+    Stale,
+}
+
+impl<T> CacheSnapshot<T> {
+    /// Whether the value is out of date because it couldn't be refreshed
+    pub fn is_stale(&self) -> bool {
+        self.freshness == Freshness::Stale
+    }
+
+    /// What was left out of the value because it couldn't be parsed
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
+    }
+}
+
+impl<T> Deref for CacheSnapshot<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> Clone for CacheSnapshot<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            freshness: self.freshness,
+            warnings: self.warnings.clone(),
+            origin: self.origin.clone(),
+        }
+    }
+}
+
+/// The version of a cached value that another value was derived from
+#[derive(Clone)]
+struct Origin {
+    version: Weak<AtomicUsize>,
+    seen: usize,
+}
+
+impl Origin {
+    /// Whether the value has been updated since.  A value whose cache has been dropped, e.g. a page
+    /// that was evicted, counts as updated, since it'll be replaced by a new entry when next used.
+    fn is_outdated(&self) -> bool {
+        self.version
+            .upgrade()
+            .is_none_or(|v| v.load(Ordering::SeqCst) != self.seen)
+    }
+}
+
+/// Hit, miss and refresh counts, shared by all of the entries of a cache map
 #[derive(Default)]
-pub struct Cache<T> {
-    timestamp: Mutex<Option<Instant>>,
-    value: RwLock<T>,
+pub struct CacheStats {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    refreshes: AtomicUsize,
+}
+
+impl CacheStats {
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// How many updates have been attempted, successful or not
+    pub fn refreshes(&self) -> usize {
+        self.refreshes.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, hit: bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Retrieves the stored value unless the storage interval has expired in which case the value is updated
+pub struct Cache<T>(Arc<CacheState<T>>);
+
+struct CacheState<T> {
+    /// Held for the duration of an update, so that concurrent callers share a single update
+    update_lock: Mutex<()>,
+    /// How many updates have finished, successfully or not
+    updates: AtomicUsize,
+    timestamp: SyncMutex<Option<Instant>>,
+    current: SyncMutex<Current<T>>,
+    /// Bumped whenever the value is replaced, so that values derived from it can tell
+    version: Arc<AtomicUsize>,
+    /// The source recorded by `derive` during the current update, which becomes `source` if it succeeds
+    next_source: SyncMutex<Option<u64>>,
+    /// Recorded by `depend_on` during the current update, and kept along with the value
+    next_dependencies: SyncMutex<Vec<Origin>>,
+    /// Recorded by `warn` during the current update, and kept along with the value
+    next_warnings: SyncMutex<Vec<ParseWarning>>,
+    revalidating: AtomicBool,
+    /// Whether the last update failed, so the value is older than it should be
+    update_failed: AtomicBool,
+    /// Set on a value that was inserted already expired, until it's updated
+    expired: AtomicBool,
+    /// Approximate size of `value` in bytes
+    size: AtomicUsize,
+    /// Measures a value for `size`
+    size_of: fn(&T) -> usize,
+    last_access: SyncMutex<Instant>,
+    stats: Arc<CacheStats>,
+    clock: SharedClock,
+}
+
+/// Replaced as a whole on each update, so a value is never seen with another value's source
+struct Current<T> {
+    value: Arc<T>,
+    version: usize,
+    /// Hash of the data the value was derived from
+    source: Option<u64>,
+    /// Other cached values this value was derived from.  If any of them is updated, so is this.
+    dependencies: Vec<Origin>,
+    warnings: Arc<Vec<ParseWarning>>,
 }
 
 impl<T> Cache<T>
 where
-    T: Default,
+    T: Default + Serialize,
 {
-    pub fn new() -> Self {
-        Self {
-            timestamp: Mutex::new(None),
-            value: RwLock::new(T::default()),
-        }
+    pub fn new(clock: SharedClock) -> Self {
+        Self::with_stats(T::default(), None, Arc::default(), approx_size, clock)
+    }
+
+    fn with_stats(
+        value: T,
+        timestamp: Option<Instant>,
+        stats: Arc<CacheStats>,
+        size_of: fn(&T) -> usize,
+        clock: SharedClock,
+    ) -> Self {
+        Self(Arc::new(CacheState {
+            update_lock: Mutex::new(()),
+            updates: AtomicUsize::new(0),
+            timestamp: SyncMutex::new(timestamp),
+            size: AtomicUsize::new(size_of(&value)),
+            size_of,
+            current: SyncMutex::new(Current {
+                value: Arc::new(value),
+                version: 0,
+                source: None,
+                dependencies: Vec::new(),
+                warnings: Arc::default(),
+            }),
+            version: Arc::new(AtomicUsize::new(0)),
+            next_source: SyncMutex::new(None),
+            next_dependencies: SyncMutex::new(Vec::new()),
+            next_warnings: SyncMutex::new(Vec::new()),
+            revalidating: AtomicBool::new(false),
+            update_failed: AtomicBool::new(false),
+            expired: AtomicBool::new(false),
+            last_access: SyncMutex::new(clock.now()),
+            stats,
+            clock,
+        }))
     }
 
     pub async fn retrieve_or_update<F, E>(
         &self,
         interval: Duration,
         update: impl Fn() -> F,
-    ) -> Result<CacheGuard<'_, T>, E>
+    ) -> Result<CacheSnapshot<T>, E>
     where
         F: Future<Output = Result<T, E>>,
     {
-        let mut timestamp = self.timestamp.lock().await;
+        self.record_lookup(interval);
+        self.refresh(interval, update, false).await?;
 
-        if timestamp.is_none() || timestamp.unwrap().elapsed() >= interval {
-            match update().await {
-                Ok(new_value) => {
-                    *self.value.write().await = new_value;
-                    *timestamp = Some(Instant::now());
-                }
-                Err(error) => return Err(error),
+        Ok(self.snapshot(Freshness::Fresh))
+    }
+
+    /// Like `retrieve_or_update`, but if the update fails and there is a previous value, that is returned instead, marked stale
+    pub async fn retrieve_or_serve_stale<F, E>(
+        &self,
+        interval: Duration,
+        update: impl Fn() -> F,
+    ) -> Result<CacheSnapshot<T>, E>
+    where
+        F: Future<Output = Result<T, E>>,
+        E: Display,
+    {
+        self.record_lookup(interval);
+        let has_value = self.timestamp().is_some();
+
+        match self.refresh(interval, update, has_value).await {
+            Ok(true) => Ok(self.snapshot(Freshness::Fresh)),
+            Ok(false) => {
+                record_outcome("stale");
+                Ok(self.snapshot(Freshness::Stale))
+            }
+            Err(error) if has_value => {
+                record_outcome("stale");
+                warn!("serving stale value after failed refresh: {}", error);
+                Ok(self.snapshot(Freshness::Stale))
             }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns an expired value immediately while it is updated in the background.  It's marked as
+    /// revalidating, or as stale if the last update failed.  Only waits on the update if there's no
+    /// previous value to return.
+    pub async fn retrieve_or_revalidate<F, E>(
+        &self,
+        interval: Duration,
+        update: impl Fn() -> F + Send + 'static,
+    ) -> Result<CacheSnapshot<T>, E>
+    where
+        F: Future<Output = Result<T, E>> + Send + 'static,
+        E: Display + Send,
+        T: Send + Sync + 'static,
+    {
+        if self.timestamp().is_none() {
+            return self.retrieve_or_update(interval, update).await;
         }
 
-        Ok(self.value.read().await)
+        let expired = self.is_expired(interval);
+        self.0.stats.record(!expired);
+
+        if !expired {
+            record_outcome("hit");
+            return Ok(self.snapshot(Freshness::Fresh));
+        }
+
+        let freshness = if self.0.update_failed.load(Ordering::SeqCst) {
+            record_outcome("stale");
+            Freshness::Stale
+        } else {
+            record_outcome("revalidate");
+            Freshness::Revalidating
+        };
+
+        // Only one background update at a time
+        if !self.0.revalidating.swap(true, Ordering::SeqCst) {
+            let cache = self.clone();
+
+            rocket::tokio::spawn(
+                async move {
+                    // Cleared even if the update panics, so the value isn't stuck without updates
+                    let _revalidating = ClearOnDrop(&cache.0.revalidating);
+
+                    if let Err(error) = cache.refresh(interval, update, true).await {
+                        warn!("background refresh failed: {}", error);
+                    }
+                }
+                .instrument(tracing::info_span!(
+                    parent: Span::current(),
+                    "revalidate",
+                    parse_ms = Empty,
+                    unchanged = Empty,
+                )),
+            );
+        }
+
+        Ok(self.snapshot(freshness))
+    }
+
+    /// Updates the value if it has expired, and returns whether it's fresh.
+    ///
+    /// Only one caller updates at a time, and callers that were waiting on that update use its result
+    /// rather than updating again.  If that update failed, those callers will try their own update,
+    /// unless `share_failure` is set, in which case they return `Ok(false)` straight away.
+    async fn refresh<F, E>(
+        &self,
+        interval: Duration,
+        update: impl Fn() -> F,
+        share_failure: bool,
+    ) -> Result<bool, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        if !self.is_expired(interval) {
+            return Ok(true);
+        }
+
+        let updates = self.0.updates.load(Ordering::SeqCst);
+        let _update_lock = self.0.update_lock.lock().await;
+
+        if !self.is_expired(interval) {
+            return Ok(true);
+        } else if share_failure && self.0.updates.load(Ordering::SeqCst) != updates {
+            return Ok(false);
+        }
+
+        *self.0.next_source.lock().unwrap() = None;
+        self.0.next_dependencies.lock().unwrap().clear();
+        self.0.next_warnings.lock().unwrap().clear();
+        let result = update().await;
+        self.0.updates.fetch_add(1, Ordering::SeqCst);
+        self.0.stats.refreshes.fetch_add(1, Ordering::Relaxed);
+        let next_source = self.0.next_source.lock().unwrap().take();
+        let next_dependencies = mem::take(&mut *self.0.next_dependencies.lock().unwrap());
+        let next_warnings = mem::take(&mut *self.0.next_warnings.lock().unwrap());
+        self.0
+            .update_failed
+            .store(result.is_err(), Ordering::SeqCst);
+        let new_value = result?;
+        self.0.expired.store(false, Ordering::SeqCst);
+
+        self.0
+            .size
+            .store((self.0.size_of)(&new_value), Ordering::Relaxed);
+        {
+            let mut current = self.0.current.lock().unwrap();
+            *current = Current {
+                value: Arc::new(new_value),
+                version: current.version + 1,
+                source: next_source,
+                dependencies: next_dependencies,
+                warnings: Arc::new(next_warnings),
+            };
+            self.0.version.store(current.version, Ordering::SeqCst);
+        }
+        *self.0.timestamp.lock().unwrap() = Some(self.0.clock.now());
+
+        Ok(true)
+    }
+
+    /// Meant to be called from within an update.  Ties the new value to the version of `snapshot`
+    /// it's being derived from, so that it expires as soon as that is updated.
+    pub fn depend_on<U>(&self, snapshot: &CacheSnapshot<U>) {
+        self.0
+            .next_dependencies
+            .lock()
+            .unwrap()
+            .push(snapshot.origin.clone());
+    }
+
+    /// Meant to be called from within an update.  Notes what was left out of the new value.
+    pub fn warn(&self, warnings: impl IntoIterator<Item = ParseWarning>) {
+        self.0.next_warnings.lock().unwrap().extend(warnings);
+    }
+
+    /// Meant to be called from within an update.  Derives a new value from `source` with `derive`,
+    /// unless the current value was derived from the same source, in which case it's reused as is.
+    pub fn derive<S, E>(&self, source: S, derive: impl FnOnce() -> Result<T, E>) -> Result<T, E>
+    where
+        S: Hash,
+        T: Clone,
+    {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        let source = hasher.finish();
+
+        *self.0.next_source.lock().unwrap() = Some(source);
+
+        let current = self.current();
+        if self.timestamp().is_some() && current.source == Some(source) {
+            Span::current().record("unchanged", true);
+            self.warn(current.warnings.iter().cloned());
+            return Ok(T::clone(&current.value));
+        }
+        drop(current);
+
+        let start = Instant::now();
+        let result = derive();
+        Span::current().record("parse_ms", start.elapsed().as_secs_f64() * 1000.0);
+
+        result
+    }
+
+    /// Returns the current value without updating it, if there is one
+    pub fn peek(&self) -> Option<CacheSnapshot<T>> {
+        self.timestamp().map(|_| self.snapshot(Freshness::Fresh))
+    }
+
+    /// How long ago the value was last updated, if it ever was
+    pub fn age(&self) -> Option<Duration> {
+        self.timestamp().map(|t| self.0.clock.since(t))
+    }
+
+    /// Approximate size of the value in bytes
+    pub fn size(&self) -> usize {
+        self.0.size.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.0.stats
+    }
+
+    /// Counts a lookup, and notes on the current span whether it'll be answered from the cache
+    fn record_lookup(&self, interval: Duration) {
+        let expired = self.is_expired(interval);
+        self.0.stats.record(!expired);
+
+        record_outcome(if !expired {
+            "hit"
+        } else if self.timestamp().is_none() {
+            "miss"
+        } else {
+            "refresh"
+        });
+    }
+
+    fn timestamp(&self) -> Option<Instant> {
+        *self.0.timestamp.lock().unwrap()
+    }
+
+    fn is_expired(&self, interval: Duration) -> bool {
+        self.0.expired.load(Ordering::SeqCst)
+            || self
+                .timestamp()
+                .is_none_or(|t| self.0.clock.since(t) >= interval)
+            || self.current().dependencies.iter().any(Origin::is_outdated)
+    }
+
+    fn touch(&self) {
+        *self.0.last_access.lock().unwrap() = self.0.clock.now();
+    }
+
+    fn last_access(&self) -> Instant {
+        *self.0.last_access.lock().unwrap()
+    }
+
+    fn current(&self) -> SyncMutexGuard<'_, Current<T>> {
+        self.0.current.lock().unwrap()
+    }
+
+    fn snapshot(&self, freshness: Freshness) -> CacheSnapshot<T> {
+        let current = self.current();
+
+        CacheSnapshot {
+            value: current.value.clone(),
+            freshness,
+            warnings: current.warnings.clone(),
+            origin: Origin {
+                version: Arc::downgrade(&self.0.version),
+                seen: current.version,
+            },
+        }
+    }
+}
+
+impl<T> Clone for Cache<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for Cache<T>
+where
+    T: Default + Serialize,
+{
+    fn default() -> Self {
+        Self::new(SystemClock::shared())
     }
 }
 
-pub type CacheMapGuard<'a, K, V> = RwLockReadGuard<'a, HashMap<K, Cache<V>>>;
+/// Notes how a lookup was answered on the current span, if it has a `cache` field
+fn record_outcome(outcome: &str) {
+    Span::current().record("cache", outcome);
+}
+
+struct ClearOnDrop<'a>(&'a AtomicBool);
+
+impl Drop for ClearOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Roughly how much memory a value takes up, going by the length of its JSON representation.
+/// This is what values are measured by unless their cache is given something cheaper.
+fn approx_size<T>(value: &T) -> usize
+where
+    T: Serialize,
+{
+    struct ByteCounter(usize);
+
+    impl io::Write for ByteCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = ByteCounter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+/// Keeps a `CacheMap` entry alive, even if it's evicted from the map while in use
+pub type CacheMapEntry<V> = Arc<Cache<V>>;
+
+/// Bounds on how much a `CacheMap` holds onto.  When either of the size limits is exceeded,
+/// the least recently used entries are evicted.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CacheLimits {
+    pub max_entries: Option<usize>,
+    /// Approximate total size of the values, in bytes
+    pub max_bytes: Option<usize>,
+    /// Seconds an entry can go without being used before it is removed
+    pub idle_ttl: Option<u64>,
+}
 
-#[derive(Default)]
 pub struct CacheMap<K, V> {
-    cache_lock: Mutex<()>,
-    cache: RwLock<HashMap<K, Cache<V>>>,
+    cache: SyncRwLock<HashMap<K, CacheMapEntry<V>>>,
+    limits: CacheLimits,
+    stats: Arc<CacheStats>,
+    size_of: fn(&V) -> usize,
+    clock: SharedClock,
 }
 
 impl<K, V> CacheMap<K, V>
 where
-    K: Eq + Hash,
-    V: Default,
+    K: Clone + Eq + Hash,
+    V: Default + Serialize,
 {
-    pub fn new() -> Self {
+    pub fn with_limits(limits: CacheLimits, clock: SharedClock) -> Self {
         Self {
-            cache_lock: Mutex::new(()),
-            cache: RwLock::new(HashMap::new()),
+            cache: SyncRwLock::new(HashMap::new()),
+            limits,
+            stats: Arc::default(),
+            size_of: approx_size,
+            clock,
         }
     }
 
-    pub async fn get(&self, key: K) -> GuardStack<'_, CacheMapGuard<'_, K, V>, Cache<V>>
-    where
-        K: Clone,
-    {
-        let _cache_lock = self.cache_lock.lock().await;
+    /// Measures values with `size_of` rather than by serializing them
+    pub fn sized_by(mut self, size_of: fn(&V) -> usize) -> Self {
+        self.size_of = size_of;
+        self
+    }
 
-        let cache_read = self.cache.read().await;
+    /// Gets the entry for `key`, creating an empty one if there isn't one.
+    /// The map is only locked for the lookup, so entries can be used independently of each other.
+    pub fn get(&self, key: K) -> CacheMapEntry<V> {
+        let existing = self.cache.read().unwrap().get(&key).cloned();
 
-        if cache_read.contains_key(&key) {
-            GuardStack::new(cache_read).map(|c| c.get(&key).unwrap())
-        } else {
-            std::mem::drop(cache_read);
-
-            {
-                let mut cache_write = self.cache.write().await;
-                cache_write.insert(key.clone(), Cache::new());
+        let entry = match existing {
+            Some(entry) => entry,
+            None => {
+                let mut cache_write = self.cache.write().unwrap();
+                let entry = cache_write
+                    .entry(key)
+                    .or_insert_with(|| {
+                        Arc::new(Cache::with_stats(
+                            V::default(),
+                            None,
+                            self.stats.clone(),
+                            self.size_of,
+                            self.clock.clone(),
+                        ))
+                    })
+                    .clone();
+                self.evict(&mut cache_write);
+                entry
             }
+        };
+
+        entry.touch();
+        entry
+    }
+
+    /// Inserts a value that was last updated at `timestamp`
+    pub fn insert(&self, key: K, value: V, timestamp: Instant) {
+        let cache = Cache::with_stats(
+            value,
+            Some(timestamp),
+            self.stats.clone(),
+            self.size_of,
+            self.clock.clone(),
+        );
+
+        self.insert_cache(key, cache);
+    }
+
+    /// Inserts a value that was updated too long ago to tell when, so that it's updated when next used
+    pub fn insert_expired(&self, key: K, value: V) {
+        let cache = Cache::with_stats(
+            value,
+            Some(self.clock.now()),
+            self.stats.clone(),
+            self.size_of,
+            self.clock.clone(),
+        );
+        cache.0.expired.store(true, Ordering::SeqCst);
+
+        self.insert_cache(key, cache);
+    }
+
+    fn insert_cache(&self, key: K, cache: Cache<V>) {
+        let mut cache_write = self.cache.write().unwrap();
+        cache_write.insert(key, Arc::new(cache));
+        self.evict(&mut cache_write);
+    }
 
-            GuardStack::new(self.cache.read().await).map(|c| c.get(&key).unwrap())
+    /// Gets the entry for `key` only if there already is one
+    pub fn get_existing(&self, key: &K) -> Option<CacheMapEntry<V>> {
+        self.cache.read().unwrap().get(key).cloned()
+    }
+
+    pub fn entries(&self) -> Vec<(K, CacheMapEntry<V>)> {
+        self.cache
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(k, c)| (k.clone(), c.clone()))
+            .collect()
+    }
+
+    /// Removes every entry whose key matches `predicate`, so that it will be fetched again when next used
+    pub fn remove_where(&self, mut predicate: impl FnMut(&K) -> bool) {
+        self.cache.write().unwrap().retain(|k, _| !predicate(k));
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// Removes the entries that have gone unused for longer than the idle limit.  New keys don't
+    /// arrive often enough to leave this to `evict`, so it's run on a timer.
+    pub fn evict_idle(&self) {
+        if let Some(idle_ttl) = self.limits.idle_ttl {
+            let idle_ttl = Duration::from_secs(idle_ttl);
+            self.cache
+                .write()
+                .unwrap()
+                .retain(|_, c| self.clock.since(c.last_access()) < idle_ttl);
+        }
+    }
+
+    /// Removes the least recently used entries until the map is within its size limits
+    fn evict(&self, cache: &mut HashMap<K, CacheMapEntry<V>>) {
+        let mut total_bytes = cache.values().map(|c| c.size()).sum::<usize>();
+
+        let is_over_limits = |entries: usize, bytes: usize| {
+            self.limits.max_entries.is_some_and(|m| entries > m)
+                || self.limits.max_bytes.is_some_and(|m| bytes > m)
+        };
+
+        // Usually a single entry has to go to make room for a new one, so finding the least
+        // recently used entry each time is cheaper than sorting the whole map.
+        while is_over_limits(cache.len(), total_bytes) {
+            let key = match cache.iter().min_by_key(|(_, c)| c.last_access()) {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+
+            if let Some(c) = cache.remove(&key) {
+                total_bytes -= c.size();
+            }
         }
     }
 }
 
-pub type PageCacheGuard<'a> =
-    GuardStack<'a, (CacheMapGuard<'a, Url, String>, CacheGuard<'a, String>), String>;
+/// A fetched page, along with what's needed to tell whether it has changed
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Page {
+    pub body: String,
+    /// Hash of the body, which changes whenever the body does
+    pub hash: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Page {
+    pub fn new(body: String, etag: Option<String>, last_modified: Option<String>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+
+        Self {
+            hash: hasher.finish(),
+            body,
+            etag,
+            last_modified,
+        }
+    }
+
+    /// Roughly how much memory the page takes up, which is nearly all body
+    fn size(&self) -> usize {
+        self.body.len()
+    }
+}
 
-pub struct PageCache(CacheMap<Url, String>);
+pub struct PageCache {
+    pages: CacheMap<Url, Page>,
+    store: Option<PageStore>,
+    /// Pages bigger than this many bytes are refused
+    max_page_size: usize,
+    clock: SharedClock,
+}
 
 impl PageCache {
-    pub fn new() -> Self {
-        Self(CacheMap::new())
+    pub fn new(limits: CacheLimits, max_page_size: usize, clock: SharedClock) -> Self {
+        Self {
+            pages: CacheMap::with_limits(limits, clock.clone()).sized_by(Page::size),
+            store: None,
+            max_page_size,
+            clock,
+        }
     }
 
-    pub async fn retrieve_or_update<F, U>(
+    /// Creates a page cache backed by `store`, seeded with every stored page younger than `max_age`
+    pub async fn with_store(
+        limits: CacheLimits,
+        max_page_size: usize,
+        store: PageStore,
+        max_age: Duration,
+        clock: SharedClock,
+    ) -> Self {
+        let pages = CacheMap::with_limits(limits, clock.clone()).sized_by(Page::size);
+
+        match store.load(clock.system_now(), max_age).await {
+            Ok(stored_pages) => {
+                for stored_page in stored_pages {
+                    let age = clock
+                        .system_now()
+                        .duration_since(stored_page.fetched)
+                        .unwrap_or_default();
+
+                    // An `Instant` may not reach back further than the host's uptime, but a page
+                    // that old is still worth keeping to revalidate with a conditional request
+                    match clock.now().checked_sub(age) {
+                        Some(timestamp) => {
+                            pages.insert(stored_page.url, stored_page.page, timestamp)
+                        }
+                        None => pages.insert_expired(stored_page.url, stored_page.page),
+                    }
+                }
+            }
+            Err(error) => warn!("could not load stored pages: {}", error),
+        }
+
+        Self {
+            pages,
+            store: Some(store),
+            max_page_size,
+            clock,
+        }
+    }
+
+    /// Sends the request built by `request` if the page at `url` has expired.  If there's already a
+    /// copy of the page, the request only asks for the page if it has changed since.
+    pub async fn retrieve_or_update<'c, U>(
         &self,
         interval: Duration,
         url: U,
-        fetch_url: impl Fn(Url) -> F,
+        request: impl Fn(Url) -> RequestBuilder<'c>,
         error: &str,
-    ) -> ScrapeResult<PageCacheGuard<'_>>
+    ) -> ScrapeResult<CacheSnapshot<Page>>
     where
-        F: Future<Output = Result<Response, Error>>,
         U: IntoUrl,
     {
         let url = url.into_url().unwrap();
 
-        self.0
-            .get(url.clone())
-            .await
-            .try_push_guard_async(|c| async move {
-                c.retrieve_or_update(interval, || async {
-                    let response = scrape_result(fetch_url(url.clone()).await, error);
-                    match response {
-                        Ok(response) => Ok(response.text().await.unwrap()),
-                        Err(error) => Err(error),
-                    }
+        let store = self.store.as_ref();
+        let max_page_size = self.max_page_size;
+        let clock = &self.clock;
+
+        let span = tracing::info_span!("page", url = %url, cache = Empty, status = Empty);
+
+        let c = self.pages.get(url.clone());
+
+        // Anything that goes wrong here fails the update, so error pages are never cached.
+        c.retrieve_or_update(interval, || async {
+            let previous = c.peek();
+
+            let mut request = request(url.clone());
+            if let Some(previous) = &previous {
+                if let Some(etag) = &previous.etag {
+                    request = request.header(IF_NONE_MATCH, etag.as_str());
+                }
+                if let Some(last_modified) = &previous.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+                }
+            }
+
+            let response = request.send().await;
+            if let Ok(response) = &response {
+                Span::current().record("status", response.status().as_u16());
+            }
+
+            let page = match (response, previous) {
+                (Ok(r), Some(previous)) if r.status() == StatusCode::NOT_MODIFIED => {
+                    Page::clone(&previous)
+                }
+                (response, _) => {
+                    let response = scrape_result(response, error)?;
+
+                    let header = |name| {
+                        response
+                            .headers()
+                            .get(name)
+                            .and_then(|v| v.to_str().ok())
+                            .map(str::to_owned)
+                    };
+                    let etag = header(ETAG);
+                    let last_modified = header(LAST_MODIFIED);
+
+                    let body = scrape_body(response, max_page_size, error).await?;
+                    Page::new(body, etag, last_modified)
+                }
+            };
+
+            if let Some(store) = store {
+                if let Err(error) = store.save(&url, clock.system_now(), &page).await {
+                    warn!("could not store page {}: {}", url, error);
+                }
+            }
+
+            Ok(page)
+        })
+        .instrument(span)
+        .await
+    }
+
+    pub fn entries(&self) -> Vec<(Url, CacheMapEntry<Page>)> {
+        self.pages.entries()
+    }
+
+    pub fn evict_idle(&self) {
+        self.pages.evict_idle();
+    }
+
+    /// Drops a page from memory and from the page store, so that it's fetched again when next used
+    pub async fn remove(&self, url: &Url) {
+        self.pages.remove_where(|u| u == url);
+
+        if let Some(store) = &self.store {
+            if let Err(error) = store.remove(url).await {
+                warn!("could not remove stored page {}: {}", url, error);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        self.pages.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{ready, Ready};
+
+    use super::*;
+    use crate::util::clock::ManualClock;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn manual_clock() -> (Arc<ManualClock>, SharedClock) {
+        let clock = Arc::new(ManualClock::default());
+        (clock.clone(), clock)
+    }
+
+    /// An update that counts how many times it's been run, failing while `failing` is set
+    #[derive(Clone, Default)]
+    struct Counter {
+        runs: Arc<AtomicUsize>,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl Counter {
+        fn update(&self) -> impl Fn() -> Ready<Result<usize, String>> + Send + 'static {
+            let counter = self.clone();
+
+            move || {
+                let runs = counter.runs.fetch_add(1, Ordering::SeqCst) + 1;
+                ready(match counter.failing.load(Ordering::SeqCst) {
+                    true => Err("failed".to_owned()),
+                    false => Ok(runs),
                 })
-                .await
-            })
+            }
+        }
+
+        fn runs(&self) -> usize {
+            self.runs.load(Ordering::SeqCst)
+        }
+
+        fn fail(&self, failing: bool) {
+            self.failing.store(failing, Ordering::SeqCst);
+        }
+
+        /// Waits for a background update to bring the run count up to `runs`
+        async fn wait_for(&self, runs: usize) {
+            for _ in 0..100 {
+                if self.runs() >= runs {
+                    return;
+                }
+                rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("update never ran");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn updates_once_the_interval_has_passed() {
+        let (clock, shared_clock) = manual_clock();
+        let cache = Cache::<usize>::new(shared_clock);
+        let counter = Counter::default();
+
+        let value = cache.retrieve_or_update(INTERVAL, counter.update()).await;
+        assert_eq!(*value.unwrap(), 1);
+
+        clock.advance(INTERVAL - SECOND);
+        let value = cache.retrieve_or_update(INTERVAL, counter.update()).await;
+        assert_eq!(*value.unwrap(), 1);
+
+        clock.advance(SECOND);
+        let value = cache.retrieve_or_update(INTERVAL, counter.update()).await;
+        assert_eq!(*value.unwrap(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn serves_stale_value_when_update_fails() {
+        let (clock, shared_clock) = manual_clock();
+        let cache = Cache::<usize>::new(shared_clock);
+        let counter = Counter::default();
+
+        cache
+            .retrieve_or_serve_stale(INTERVAL, counter.update())
+            .await
+            .unwrap();
+
+        clock.advance(INTERVAL);
+        counter.fail(true);
+        let value = cache
+            .retrieve_or_serve_stale(INTERVAL, counter.update())
             .await
+            .unwrap();
+        assert_eq!(*value, 1);
+        assert!(value.is_stale());
+
+        counter.fail(false);
+        let value = cache
+            .retrieve_or_serve_stale(INTERVAL, counter.update())
+            .await
+            .unwrap();
+        assert_eq!(*value, 3);
+        assert!(!value.is_stale());
     }
-}
 
-impl Default for PageCache {
-    fn default() -> Self {
-        Self::new()
+    #[rocket::async_test]
+    async fn fails_without_a_value_to_serve() {
+        let (_, shared_clock) = manual_clock();
+        let cache = Cache::<usize>::new(shared_clock);
+        let counter = Counter::default();
+
+        counter.fail(true);
+        let value = cache
+            .retrieve_or_serve_stale(INTERVAL, counter.update())
+            .await;
+        assert!(value.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn revalidates_in_the_background() {
+        let (clock, shared_clock) = manual_clock();
+        let cache = Cache::<usize>::new(shared_clock);
+        let counter = Counter::default();
+
+        cache
+            .retrieve_or_revalidate(INTERVAL, counter.update())
+            .await
+            .unwrap();
+
+        // An expired value is returned straight away, but isn't stale while upstream is fine.
+        clock.advance(INTERVAL);
+        let value = cache
+            .retrieve_or_revalidate(INTERVAL, counter.update())
+            .await
+            .unwrap();
+        assert_eq!(*value, 1);
+        assert!(!value.is_stale());
+
+        counter.wait_for(2).await;
+        assert_eq!(*cache.peek().unwrap(), 2);
+
+        clock.advance(INTERVAL);
+        counter.fail(true);
+        cache
+            .retrieve_or_revalidate(INTERVAL, counter.update())
+            .await
+            .unwrap();
+        counter.wait_for(3).await;
+
+        // Once a background update has failed, the value is stale.
+        let value = cache
+            .retrieve_or_revalidate(INTERVAL, counter.update())
+            .await
+            .unwrap();
+        assert_eq!(*value, 2);
+        assert!(value.is_stale());
+    }
+
+    #[rocket::async_test]
+    async fn expires_with_the_value_it_depends_on() {
+        let (clock, shared_clock) = manual_clock();
+        let sources =
+            CacheMap::<usize, usize>::with_limits(Default::default(), shared_clock.clone());
+        let derived = Cache::<usize>::new(shared_clock);
+        let source_counter = Counter::default();
+        let derived_counter = Counter::default();
+
+        let derive = |source: CacheSnapshot<usize>| {
+            let update = derived_counter.update();
+            let derived = &derived;
+            move || {
+                derived.depend_on(&source);
+                update()
+            }
+        };
+
+        let source = sources
+            .get(0)
+            .retrieve_or_update(INTERVAL, source_counter.update())
+            .await
+            .unwrap();
+        derived
+            .retrieve_or_update(Duration::MAX, derive(source.clone()))
+            .await
+            .unwrap();
+
+        // Updating the source expires the derived value, however long it would have been kept.
+        clock.advance(INTERVAL);
+        let source = sources
+            .get(0)
+            .retrieve_or_update(INTERVAL, source_counter.update())
+            .await
+            .unwrap();
+        derived
+            .retrieve_or_update(Duration::MAX, derive(source.clone()))
+            .await
+            .unwrap();
+        assert_eq!(derived_counter.runs(), 2);
+
+        derived
+            .retrieve_or_update(Duration::MAX, derive(source.clone()))
+            .await
+            .unwrap();
+        assert_eq!(derived_counter.runs(), 2);
+
+        // So does dropping the source, since it'll be replaced by a new entry.
+        sources.remove_where(|_| true);
+        derived
+            .retrieve_or_update(Duration::MAX, derive(source.clone()))
+            .await
+            .unwrap();
+        assert_eq!(derived_counter.runs(), 3);
+    }
+
+    fn keys(map: &CacheMap<usize, usize>) -> Vec<usize> {
+        let mut keys = map
+            .entries()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[rocket::async_test]
+    async fn keeps_stored_pages_older_than_the_clock_can_reach() {
+        let (_, shared_clock) = manual_clock();
+        let dir = std::env::temp_dir().join(format!("page-store-test-{}", std::process::id()));
+        let url = Url::parse("https://example.com/page").unwrap();
+
+        let store = PageStore::open(&dir).await.unwrap();
+        let page = Page::new("body".to_owned(), Some("etag".to_owned()), None);
+        store
+            .save(&url, std::time::UNIX_EPOCH, &page)
+            .await
+            .unwrap();
+
+        let pages = PageCache::with_store(
+            CacheLimits::default(),
+            usize::MAX,
+            store,
+            Duration::MAX,
+            shared_clock,
+        )
+        .await;
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let entries = pages.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].1.peek().unwrap().value.etag.as_deref(),
+            Some("etag")
+        );
+        assert!(entries[0].1.is_expired(INTERVAL));
+    }
+
+    #[test]
+    fn evicts_idle_entries() {
+        let (clock, shared_clock) = manual_clock();
+        let limits = CacheLimits {
+            idle_ttl: Some(INTERVAL.as_secs()),
+            ..Default::default()
+        };
+        let map = CacheMap::<usize, usize>::with_limits(limits, shared_clock);
+
+        map.get(1);
+        clock.advance(INTERVAL - SECOND);
+        map.get(2);
+        clock.advance(SECOND);
+        map.get(3);
+        assert_eq!(keys(&map), [1, 2, 3]);
+
+        map.evict_idle();
+        assert_eq!(keys(&map), [2, 3]);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries() {
+        let (clock, shared_clock) = manual_clock();
+        let limits = CacheLimits {
+            max_entries: Some(2),
+            ..Default::default()
+        };
+        let map = CacheMap::<usize, usize>::with_limits(limits, shared_clock);
+
+        map.get(1);
+        clock.advance(SECOND);
+        map.get(2);
+        clock.advance(SECOND);
+        map.get(1);
+        clock.advance(SECOND);
+        map.get(3);
+
+        assert_eq!(keys(&map), [1, 3]);
+    }
+
+    #[test]
+    fn evicts_entries_over_the_byte_budget() {
+        let (clock, shared_clock) = manual_clock();
+        let limits = CacheLimits {
+            max_bytes: Some(5),
+            ..Default::default()
+        };
+        let map = CacheMap::<usize, usize>::with_limits(limits, shared_clock.clone());
+
+        // Each of these serializes to 3 bytes.
+        map.insert(1, 100, shared_clock.now());
+        clock.advance(SECOND);
+        map.insert(2, 200, shared_clock.now());
+
+        assert_eq!(keys(&map), [2]);
     }
 }
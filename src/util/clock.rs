@@ -0,0 +1,106 @@
+//! Where the caches get the current time from.  Expiry normally follows the real time, but it can
+//! be driven by hand instead, or sped up from some other starting time, so that refreshes and
+//! evictions happen without waiting for them.
+
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The wall clock time, for times that outlive the process, like when a stored page was fetched
+    fn system_now(&self) -> SystemTime;
+
+    /// How long it's been since `earlier`, or nothing if `earlier` is in the future
+    fn since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The real time
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> SharedClock {
+        Arc::new(Self)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that starts from `start` and runs `rate` times as fast as the real time, for replaying a
+/// day of tournaments in less than a day.  Only the times the caches read go by faster; anything
+/// that sleeps, like cache warming, still waits in real time.
+pub struct ScaledClock {
+    origin: Instant,
+    start: SystemTime,
+    rate: f64,
+}
+
+impl ScaledClock {
+    pub fn shared(start: SystemTime, rate: f64) -> SharedClock {
+        Arc::new(Self {
+            origin: Instant::now(),
+            start,
+            rate,
+        })
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.origin.elapsed().mul_f64(self.rate)
+    }
+}
+
+impl Clock for ScaledClock {
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.start + self.elapsed()
+    }
+}
+
+/// A clock that stands still until it's moved forward, for stepping through cache expiry
+#[cfg(test)]
+pub struct ManualClock(Mutex<(Instant, SystemTime)>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.0.lock().unwrap();
+        now.0 += duration;
+        now.1 += duration;
+    }
+}
+
+#[cfg(test)]
+impl Default for ManualClock {
+    /// Starts at the real time
+    fn default() -> Self {
+        Self(Mutex::new((Instant::now(), SystemTime::now())))
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().0
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.0.lock().unwrap().1
+    }
+}
@@ -0,0 +1,12 @@
+use std::io;
+use std::path::Path;
+
+use async_std::fs;
+
+/// Writes to a temporary file next to `path` first, so that a crash never leaves a half-written
+/// file behind
+pub async fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, contents).await?;
+    fs::rename(&temp_path, path).await
+}
@@ -0,0 +1,84 @@
+//! Sets up where the spans and events from the scrape pipeline go.  With `log_format = "json"`,
+//! everything, including Rocket's own log messages, is written to stdout as one JSON object per
+//! line.  Otherwise Rocket keeps its usual output, and spans are written alongside it as text.
+
+use rocket::config::LogLevel;
+use rocket::serde::Deserialize;
+use rocket::Config;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Registry;
+use yansi::Paint;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Installs the subscriber for the configured log format.  This has to happen before Rocket is
+/// built, or Rocket will have already claimed the `log` crate's logger for itself.
+pub fn init() {
+    let figment = Config::figment();
+
+    let log_format = match figment.extract_inner::<LogFormat>("log_format") {
+        Ok(log_format) => log_format,
+        Err(error) if error.missing() => LogFormat::Text,
+        Err(error) => {
+            eprintln!("invalid log format, using text: {}", error);
+            LogFormat::Text
+        }
+    };
+
+    let level = match figment
+        .extract_inner::<LogLevel>("log_level")
+        .unwrap_or(Config::default().log_level)
+    {
+        LogLevel::Critical => LevelFilter::WARN,
+        LogLevel::Normal => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::TRACE,
+        LogLevel::Off => LevelFilter::OFF,
+    };
+
+    // Our dependencies trace every connection they make, which is too much even for debugging.
+    let filter = Targets::new()
+        .with_default(level)
+        .with_target("hyper", LevelFilter::WARN)
+        .with_target("h2", LevelFilter::WARN);
+
+    let subscriber = Registry::default().with(filter);
+
+    let result = match log_format {
+        // Only our own events go through here, so that Rocket's messages look the way they always have.
+        LogFormat::Text => tracing::subscriber::set_global_default(
+            subscriber.with(tracing_subscriber::fmt::layer().with_target(false)),
+        )
+        .map_err(|e| e.to_string()),
+        // Spans are logged as they close, with how long they took, so a slow page can be followed
+        // through every step.
+        LogFormat::Json => {
+            // Rocket only turns its colors off when it's in charge of logging.
+            Paint::disable();
+
+            subscriber
+                .with(
+                    tracing_subscriber::fmt::layer()
+                        .json()
+                        .with_current_span(true)
+                        .with_span_list(true)
+                        .with_span_events(FmtSpan::CLOSE),
+                )
+                .try_init()
+                .map_err(|e| e.to_string())
+        }
+    };
+
+    if let Err(error) = result {
+        eprintln!("could not set up tracing: {}", error);
+    }
+}
@@ -0,0 +1,337 @@
+//! Counts and times what we do upstream and what visitors ask of us, for Prometheus to scrape.
+//! Everything is recorded into one global registry, since it's touched from places that have no
+//! access to managed state.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+
+use crate::util::cache::CacheStats;
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+#[derive(Default)]
+pub struct Metrics {
+    /// By upstream page and outcome
+    upstream_requests: Family<Counter>,
+    /// By upstream page
+    upstream_duration: Family<Histogram>,
+    /// By upstream page
+    captchas: Family<Counter>,
+    /// By route
+    endpoint_duration: Family<Histogram>,
+}
+
+impl Metrics {
+    /// Records a request sent upstream.  The outcome is the status code, or the kind of error if
+    /// there was no response.
+    pub fn record_upstream_request(&self, url: &Url, outcome: &str, duration: Duration) {
+        let page = upstream_page(url);
+
+        self.upstream_requests
+            .with(&[("page", page), ("outcome", outcome)])
+            .increment();
+        self.upstream_duration
+            .with(&[("page", page)])
+            .observe(duration);
+    }
+
+    /// Records that we were asked to solve a captcha instead of getting the page at `url`
+    pub fn record_captcha(&self, url: &Url) {
+        self.captchas
+            .with(&[("page", upstream_page(url))])
+            .increment();
+    }
+
+    /// Renders every metric in the Prometheus text format, along with the stats of `caches`
+    pub fn render<'a>(
+        &self,
+        caches: impl IntoIterator<Item = (&'a str, &'a CacheStats)>,
+    ) -> String {
+        let mut output = String::new();
+
+        self.upstream_requests
+            .render(
+                &mut output,
+                "upstream_requests_total",
+                "Requests sent to the upstream",
+            )
+            .unwrap();
+        self.upstream_duration
+            .render(
+                &mut output,
+                "upstream_request_duration_seconds",
+                "Time taken for the upstream to respond",
+            )
+            .unwrap();
+        self.captchas
+            .render(
+                &mut output,
+                "upstream_captchas_total",
+                "Captchas the upstream asked us to solve",
+            )
+            .unwrap();
+        self.endpoint_duration
+            .render(
+                &mut output,
+                "endpoint_response_duration_seconds",
+                "Time taken to respond to visitors",
+            )
+            .unwrap();
+
+        let caches = caches.into_iter().collect::<Vec<_>>();
+        let mut render_caches = |name, help, value: fn(&CacheStats) -> usize| {
+            let family = Family::<Counter>::default();
+            for (cache, stats) in caches.iter() {
+                family.with(&[("cache", cache)]).0 = value(stats) as u64;
+            }
+            family.render(&mut output, name, help).unwrap();
+        };
+
+        render_caches(
+            "cache_hits_total",
+            "Lookups answered from the cache",
+            CacheStats::hits,
+        );
+        render_caches(
+            "cache_misses_total",
+            "Lookups that found nothing fresh",
+            CacheStats::misses,
+        );
+        render_caches(
+            "cache_refreshes_total",
+            "Updates of cached values",
+            CacheStats::refreshes,
+        );
+
+        output
+    }
+}
+
+/// Times every response, by the route that handled it
+pub struct EndpointTimer;
+
+/// When the request being handled arrived
+struct RequestStart(Instant);
+
+#[rocket::async_trait]
+impl Fairing for EndpointTimer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Endpoint Timer",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, _: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let route = request
+            .route()
+            .map(|r| r.uri.to_string())
+            .unwrap_or_else(|| "unmatched".to_owned());
+
+        METRICS
+            .endpoint_duration
+            .with(&[("route", &route)])
+            .observe(start.0.elapsed());
+    }
+}
+
+/// The kind of upstream page at `url`, coarse enough to be used as a label
+fn upstream_page(url: &Url) -> &'static str {
+    let path = url.path();
+
+    if path.contains("pbt_tlisting") {
+        "tlisting"
+    } else if path.contains("tournamentinfo") {
+        "tournamentinfo"
+    } else if path.contains("cinfo") {
+        "cinfo"
+    } else if path.contains("show.pl") || path.starts_with("/Tournaments/") {
+        // Bracket links are to show.pl, but we fetch the bracket files it shows directly.
+        "show.pl"
+    } else if path.contains("rptbrackets") {
+        "rptbrackets"
+    } else if path.contains("ereport") {
+        "ereport"
+    } else {
+        "other"
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+/// One metric, split up by the values of its labels
+struct Family<M>(Mutex<BTreeMap<Labels, M>>);
+
+impl<M> Default for Family<M> {
+    fn default() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+}
+
+impl<M> Family<M>
+where
+    M: Metric,
+{
+    fn with(&self, labels: &[(&str, &str)]) -> FamilyMember<'_, M> {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Labels>();
+
+        let mut family = self.0.lock().unwrap();
+        family.entry(labels.clone()).or_default();
+
+        FamilyMember { family, labels }
+    }
+
+    fn render(&self, output: &mut String, name: &str, help: &str) -> fmt::Result {
+        writeln!(output, "# HELP {} {}", name, help)?;
+        writeln!(output, "# TYPE {} {}", name, M::TYPE)?;
+
+        for (labels, metric) in self.0.lock().unwrap().iter() {
+            metric.render(output, name, labels)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Holds the family locked while one of its members is being changed
+struct FamilyMember<'a, M> {
+    family: MutexGuard<'a, BTreeMap<Labels, M>>,
+    labels: Labels,
+}
+
+impl<M> Deref for FamilyMember<'_, M> {
+    type Target = M;
+
+    fn deref(&self) -> &M {
+        &self.family[&self.labels]
+    }
+}
+
+impl<M> DerefMut for FamilyMember<'_, M> {
+    fn deref_mut(&mut self) -> &mut M {
+        self.family.get_mut(&self.labels).unwrap()
+    }
+}
+
+trait Metric: Default {
+    const TYPE: &'static str;
+
+    fn render(&self, output: &mut String, name: &str, labels: &Labels) -> fmt::Result;
+}
+
+#[derive(Default)]
+struct Counter(u64);
+
+impl Counter {
+    fn increment(&mut self) {
+        self.0 += 1;
+    }
+}
+
+impl Metric for Counter {
+    const TYPE: &'static str = "counter";
+
+    fn render(&self, output: &mut String, name: &str, labels: &Labels) -> fmt::Result {
+        writeln!(output, "{}{} {}", name, format_labels(labels, None), self.0)
+    }
+}
+
+/// Upper bounds of the histogram buckets, in seconds
+const BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations in each bucket, not including the ones below it
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        if let Some(bucket) = BUCKETS.iter().position(|b| seconds <= *b) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+impl Metric for Histogram {
+    const TYPE: &'static str = "histogram";
+
+    fn render(&self, output: &mut String, name: &str, labels: &Labels) -> fmt::Result {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += count;
+            writeln!(
+                output,
+                "{}_bucket{} {}",
+                name,
+                format_labels(labels, Some(&bound.to_string())),
+                cumulative
+            )?;
+        }
+
+        writeln!(
+            output,
+            "{}_bucket{} {}",
+            name,
+            format_labels(labels, Some("+Inf")),
+            self.count
+        )?;
+        writeln!(
+            output,
+            "{}_sum{} {}",
+            name,
+            format_labels(labels, None),
+            self.sum
+        )?;
+        writeln!(
+            output,
+            "{}_count{} {}",
+            name,
+            format_labels(labels, None),
+            self.count
+        )
+    }
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let labels = labels
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect::<Vec<_>>();
+
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
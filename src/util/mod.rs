@@ -1,2 +1,8 @@
 pub mod cache;
-pub mod guard_stack;
+pub mod clock;
+pub mod file;
+pub mod logging;
+pub mod metrics;
+pub mod page_store;
+pub mod rate_limit;
+pub mod session_store;
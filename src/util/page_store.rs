@@ -0,0 +1,123 @@
+//! Keeps a copy of every fetched page on disk so that the page cache survives restarts.
+//! Each page is stored as its own JSON file, named after a hash of its URL.
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_std::fs;
+use async_std::prelude::*;
+use reqwest::Url;
+use rocket::serde::json::serde_json;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::util::cache::Page;
+use crate::util::file::write_atomically;
+
+pub struct PageStore {
+    dir: PathBuf,
+}
+
+pub struct StoredPage {
+    pub url: Url,
+    pub fetched: SystemTime,
+    pub page: Page,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct PageFile {
+    url: String,
+    /// Seconds since the Unix epoch
+    fetched: u64,
+    body: String,
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+}
+
+impl PageStore {
+    pub async fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir).await?;
+        Ok(Self { dir })
+    }
+
+    /// Loads every stored page younger than `max_age` as of `now`, deleting the rest
+    pub async fn load(&self, now: SystemTime, max_age: Duration) -> io::Result<Vec<StoredPage>> {
+        let mut pages = Vec::new();
+
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next().await {
+            let path: PathBuf = entry?.path().into();
+            if path.extension() != Some(OsStr::new("json")) {
+                continue;
+            }
+
+            match read_page(&path).await {
+                Ok(page) if page_age(&page, now) < max_age => pages.push(page),
+                Ok(_) => {
+                    let _ = fs::remove_file(&path).await;
+                }
+                Err(error) => {
+                    warn!("discarding unreadable stored page {:?}: {}", path, error);
+                    let _ = fs::remove_file(&path).await;
+                }
+            }
+        }
+
+        Ok(pages)
+    }
+
+    pub async fn save(&self, url: &Url, fetched: SystemTime, page: &Page) -> io::Result<()> {
+        let page_file = PageFile {
+            url: url.to_string(),
+            fetched: fetched
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            body: page.body.clone(),
+            etag: page.etag.clone(),
+            last_modified: page.last_modified.clone(),
+        };
+        let contents = serde_json::to_vec(&page_file)?;
+
+        write_atomically(&self.page_path(url), contents).await
+    }
+
+    pub async fn remove(&self, url: &Url) -> io::Result<()> {
+        match fs::remove_file(self.page_path(url)).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    fn page_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{:016x}.json", url_hash(url)))
+    }
+}
+
+async fn read_page(path: &Path) -> io::Result<StoredPage> {
+    let contents = fs::read(path).await?;
+    let page_file = serde_json::from_slice::<PageFile>(&contents)?;
+
+    Ok(StoredPage {
+        url: Url::parse(&page_file.url)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        fetched: UNIX_EPOCH + Duration::from_secs(page_file.fetched),
+        page: Page::new(page_file.body, page_file.etag, page_file.last_modified),
+    })
+}
+
+fn page_age(page: &StoredPage, now: SystemTime) -> Duration {
+    now.duration_since(page.fetched).unwrap_or_default()
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is guaranteed to be stable between builds
+fn url_hash(url: &Url) -> u64 {
+    url.as_str().bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
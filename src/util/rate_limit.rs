@@ -0,0 +1,140 @@
+//! Keeps us from bursting requests at pickleballtournaments.com, which gets us captcha'd.
+//! Requests are paced by a token bucket and only so many may be in flight at once.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocket::fairing::AdHoc;
+use rocket::serde::Deserialize;
+use rocket::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct RateLimits {
+    /// Requests per second allowed over the long run
+    pub requests_per_second: f64,
+    /// How many requests may be sent back to back after a quiet period
+    pub burst: u32,
+    pub max_in_flight: usize,
+    /// How many times a GET that timed out or got a server error is tried again
+    pub max_retries: u32,
+    /// Milliseconds to wait before the first retry.  Each retry waits up to twice as long as the last.
+    pub retry_base_delay: u64,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 2.0,
+            burst: 5,
+            max_in_flight: 4,
+            max_retries: 2,
+            retry_base_delay: 500,
+        }
+    }
+}
+
+/// A cheaply cloneable handle to a limiter shared by every client
+#[derive(Clone)]
+pub struct RateLimiter(Arc<RateLimiterState>);
+
+struct RateLimiterState {
+    limits: RateLimits,
+    bucket: Mutex<TokenBucket>,
+    in_flight: Arc<Semaphore>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Holds one of the in-flight slots until it's dropped
+pub struct RateLimitPermit(#[allow(dead_code)] OwnedSemaphorePermit);
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self(Arc::new(RateLimiterState {
+            limits,
+            bucket: Mutex::new(TokenBucket {
+                tokens: limits.burst as f64,
+                refilled: Instant::now(),
+            }),
+            in_flight: Arc::new(Semaphore::new(limits.max_in_flight)),
+        }))
+    }
+
+    /// Reads the limits from the Rocket configuration and puts the limiter in managed state
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Upstream Rate Limiter", |rocket| async {
+            let limits = match rocket
+                .figment()
+                .extract_inner::<RateLimits>("upstream_limits")
+            {
+                Ok(limits) => limits,
+                Err(error) if error.missing() => RateLimits::default(),
+                Err(error) => {
+                    error!("invalid upstream limits: {}", error);
+                    return Err(rocket);
+                }
+            };
+
+            if limits.requests_per_second <= 0.0 || limits.burst == 0 || limits.max_in_flight == 0 {
+                error!(
+                    "upstream limits must allow at least some requests: {:?}",
+                    limits
+                );
+                return Err(rocket);
+            }
+
+            Ok(rocket.manage(RateLimiter::new(limits)))
+        })
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.0.limits
+    }
+
+    /// Waits until a request may be sent
+    pub async fn acquire(&self) -> RateLimitPermit {
+        loop {
+            let wait = {
+                let mut bucket = self.0.bucket.lock().unwrap();
+
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.refilled).as_secs_f64()
+                    * self.0.limits.requests_per_second;
+                bucket.tokens = (bucket.tokens + refill).min(self.0.limits.burst as f64);
+                bucket.refilled = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    break;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.0.limits.requests_per_second)
+            };
+
+            async_std::task::sleep(wait).await;
+        }
+
+        let permit = self.0.in_flight.clone().acquire_owned().await.unwrap();
+        RateLimitPermit(permit)
+    }
+
+    /// How long to wait before retrying for the `attempt`th time, starting at 1.  The delay is picked
+    /// at random up to the backoff, so that requests that failed together don't retry together.
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .0
+            .limits
+            .retry_base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+
+        let random = RandomState::new().build_hasher().finish();
+        Duration::from_millis(random % (backoff + 1))
+    }
+}
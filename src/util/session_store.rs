@@ -0,0 +1,216 @@
+//! Keeps a session with pickleballtournaments.com for requests that aren't made on behalf of a
+//! visitor, like cache warming.  The session's cookies are saved to disk so that it survives restarts.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_std::fs;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use rocket::fairing::AdHoc;
+use rocket::serde::json::serde_json;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::client::Upstream;
+use crate::util::file::write_atomically;
+
+/// A cheaply cloneable handle to the server's upstream session
+#[derive(Clone)]
+pub struct SessionStore(Arc<SessionState>);
+
+struct SessionState {
+    path: Option<PathBuf>,
+    origin: Url,
+    jar: Jar,
+    /// The latest Set-Cookie value for each cookie, since the jar can't be read back out
+    set_cookies: Mutex<BTreeMap<String, String>>,
+    last_valid: Mutex<Option<SystemTime>>,
+    last_captcha: Mutex<Option<SystemTime>>,
+    dirty: AtomicBool,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SessionFile {
+    set_cookies: Vec<String>,
+    /// Seconds since the Unix epoch
+    last_valid: Option<u64>,
+    /// Seconds since the Unix epoch
+    last_captcha: Option<u64>,
+}
+
+impl SessionStore {
+    /// Opens the session saved at `path`, or starts a new one if there isn't one yet.  Without a path
+    /// the session is only kept in memory.
+    pub async fn open(path: Option<PathBuf>, upstream: &Upstream) -> io::Result<Self> {
+        let session_file = match &path {
+            Some(path) => match fs::read(path).await {
+                Ok(contents) => serde_json::from_slice::<SessionFile>(&contents)?,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => SessionFile::default(),
+                Err(error) => return Err(error),
+            },
+            None => SessionFile::default(),
+        };
+
+        let session = Self(Arc::new(SessionState {
+            path,
+            origin: upstream.origin().clone(),
+            jar: Jar::default(),
+            set_cookies: Mutex::new(BTreeMap::new()),
+            last_valid: Mutex::new(session_file.last_valid.map(from_secs)),
+            last_captcha: Mutex::new(session_file.last_captcha.map(from_secs)),
+            dirty: AtomicBool::new(false),
+        }));
+
+        let set_cookies = session_file
+            .set_cookies
+            .iter()
+            .filter_map(|c| HeaderValue::from_str(c).ok())
+            .collect::<Vec<_>>();
+        session.set_cookies(&mut set_cookies.iter(), &session.0.origin.clone());
+
+        Ok(session)
+    }
+
+    /// Reads `session_file` from the Rocket configuration and puts the session in managed state
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Upstream Session", |rocket| async {
+            let path = match rocket
+                .figment()
+                .extract_inner::<Option<PathBuf>>("session_file")
+            {
+                Ok(path) => path,
+                Err(error) if error.missing() => None,
+                Err(error) => {
+                    error!("invalid session file: {}", error);
+                    return Err(rocket);
+                }
+            };
+
+            let upstream = match rocket.state::<Upstream>() {
+                Some(upstream) => upstream.clone(),
+                None => {
+                    error!("the upstream session needs the upstream to be managed");
+                    return Err(rocket);
+                }
+            };
+
+            match SessionStore::open(path, &upstream).await {
+                Ok(session) => Ok(rocket.manage(session)),
+                Err(error) => {
+                    error!("could not open the upstream session: {}", error);
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    /// Notes whether the upstream accepted the session for a request, and saves the session if
+    /// anything about it has changed
+    pub async fn record(&self, captcha: bool) {
+        let now = SystemTime::now();
+
+        if captcha {
+            *self.0.last_captcha.lock().unwrap() = Some(now);
+            self.0.dirty.store(true, Ordering::Relaxed);
+        } else {
+            let mut last_valid = self.0.last_valid.lock().unwrap();
+
+            // Don't write the session out for every request just to bump the time it was last valid.
+            let is_outdated = last_valid
+                .and_then(|t| now.duration_since(t).ok())
+                .is_none_or(|age| age > SAVE_INTERVAL);
+            if is_outdated {
+                self.0.dirty.store(true, Ordering::Relaxed);
+            }
+
+            *last_valid = Some(now);
+        }
+
+        if self.0.dirty.swap(false, Ordering::Relaxed) {
+            if let Err(error) = self.save().await {
+                warn!("could not save the upstream session: {}", error);
+            }
+        }
+    }
+
+    /// When the upstream last served a page for this session without asking for a captcha
+    pub fn last_valid(&self) -> Option<SystemTime> {
+        *self.0.last_valid.lock().unwrap()
+    }
+
+    pub fn last_captcha(&self) -> Option<SystemTime> {
+        *self.0.last_captcha.lock().unwrap()
+    }
+
+    pub fn cookie_count(&self) -> usize {
+        self.0.set_cookies.lock().unwrap().len()
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.0.path.is_some()
+    }
+
+    async fn save(&self) -> io::Result<()> {
+        let path = match &self.0.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let session_file = SessionFile {
+            set_cookies: self
+                .0
+                .set_cookies
+                .lock()
+                .unwrap()
+                .values()
+                .cloned()
+                .collect(),
+            last_valid: self.last_valid().map(to_secs),
+            last_captcha: self.last_captcha().map(to_secs),
+        };
+        let contents = serde_json::to_vec(&session_file)?;
+
+        write_atomically(path, contents).await
+    }
+}
+
+impl CookieStore for SessionStore {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookie_headers = cookie_headers.collect::<Vec<_>>();
+
+        {
+            let mut set_cookies = self.0.set_cookies.lock().unwrap();
+            for header in cookie_headers.iter() {
+                if let Some((name, _)) = header.to_str().ok().and_then(|h| h.split_once('=')) {
+                    set_cookies.insert(name.trim().to_owned(), header.to_str().unwrap().to_owned());
+                    self.0.dirty.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+
+        self.0.jar.set_cookies(&mut cookie_headers.into_iter(), url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        self.0.jar.cookies(url)
+    }
+}
+
+/// How stale the saved time that the session was last valid may get
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn from_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
@@ -33,6 +33,8 @@ fetch(`/tournament/${tournamentId}/event/${eventName}/data`)
 class Main {
   view() {
     return [
+      eventData.stale ? m(StaleNotice) : [],
+      m(WarningNotice, { warnings: eventData.warnings }),
       m(EventInfo),
       eventData.bracket ? [
         eventData.bracket.hasOwnProperty("doubleElim") ? m(DoubleElimBracket) : m(RoundRobinBracket),
@@ -49,7 +51,7 @@ class EventInfo {
       m("h2.event-name", eventData.name),
       eventData.scheduleItem ? [
         m("p.event-date", printDate(eventData.scheduleItem.date, true)),
-        m("p.event-time", eventData.scheduleItem.time),
+        m("p.event-time", printScheduleTime(eventData.scheduleItem)),
         m("p.event-venue", eventData.scheduleItem.venue),
       ] : [],
     ]);
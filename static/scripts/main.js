@@ -79,6 +79,38 @@ class Error {
   }
 }
 
+class StaleNotice {
+  view() {
+    return m("p.stale-notice", [
+      "Couldn't reach ",
+      m("a", { href: "https://www.pickleballtournaments.com" }, "PickleballTournaments.com"),
+      ", so some of this may be out of date.",
+    ]);
+  }
+}
+
+class WarningNotice {
+  view(vnode) {
+    let kinds = new Set(vnode.attrs.warnings.map(w => w.kind));
+
+    let messages = [];
+    if (kinds.has("unmatchedPlayer")) {
+      messages.push("Some players could not be matched.");
+    }
+    if (kinds.has("malformedSchedule")) {
+      messages.push("Some of the schedule could not be read.");
+    }
+    if (kinds.has("malformedEventList")) {
+      messages.push("Some events could not be read.");
+    }
+    if (kinds.has("malformedListing") || kinds.has("unknownRegistrationStatus")) {
+      messages.push("Some tournament details could not be read.");
+    }
+
+    return messages.length > 0 ? m("p.warning-notice", messages.join(" ")) : [];
+  }
+}
+
 class LazyImage {
   constructor(vnode) {
     this.loaded = false;
@@ -140,11 +172,15 @@ class RegistrationStatus {
       } else if (s == "openSoon") {
         registrationClass = "open-soon";
         registrationType = "Opens soon";
-        detail = `Registration opens ${printDate(status[s].startDate)} at ${status[s].startTime}`;
+        detail = `Registration opens ${printDate(status[s].startDate)} at ${printTime(status[s].startTime, status[s].startTimeZone)}`;
       } else if (s == "closedToNew") {
         registrationClass = "closed";
         registrationType = "Closed to new registrations";
-        detail = `Payment deadline: ${status[s].paymentDeadline}`;
+        detail = `Payment deadline: ${printDate(status[s].paymentDeadline)}`;
+      } else if (s == "unknown") {
+        registrationClass = "closed";
+        registrationType = "Unknown";
+        detail = status[s].raw;
       }
     }
 
@@ -204,4 +240,24 @@ function printDate(isoDate, pretty = false) {
       day: "numeric",
     },
   );
+}
+
+function printTime(isoTime, timeZone) {
+  let [hours, minutes] = isoTime.split(":").map(Number);
+  let local = new Date();
+  local.setHours(hours, minutes, 0, 0);
+
+  let time = local.toLocaleString(
+    "default",
+    {
+      hour: "numeric",
+      minute: "2-digit",
+    },
+  );
+
+  return timeZone ? `${time} ${timeZone}` : time;
+}
+
+function printScheduleTime(scheduleItem) {
+  return scheduleItem.time !== null ? printTime(scheduleItem.time, scheduleItem.timeZone) : scheduleItem.rawTime;
 }
\ No newline at end of file
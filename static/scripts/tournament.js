@@ -34,6 +34,8 @@ class Main {
       `<span class="date">${printDate(listing.startDate, true)}</span>`;
 
     return [
+      tournamentData.stale ? m(StaleNotice) : [],
+      m(WarningNotice, { warnings: tournamentData.warnings }),
       m("section#listing", [
         m("a#original-link", {
           href: `https://www.pickleballtournaments.com/tournamentinfo.pl?tid=${tournamentId}`,
@@ -41,7 +43,7 @@ class Main {
         }, "View on PickleballTournaments.com"),
         tournamentData.listing.logoUrl !== null ? [m("img", { src: tournamentData.listing.logoUrl })] : [],
         m("h2.name", tournamentData.listing.name),
-        m("p.location", tournamentData.listing.location),
+        tournamentData.listing.location !== null ? m("p.location", tournamentData.listing.location) : [],
         m("p.dates", m.trust(datesString)),
         m(RegistrationStatus, { status: tournamentData.listing.registrationStatus }),
       ]),
@@ -104,9 +106,11 @@ class Schedule {
         eventDates.push([o.date, []]);
       }
 
+      // Items without a time are grouped by what was written in its place.
+      let time = o.time !== null ? o.time : o.rawTime;
       let eventTimes = eventDates[eventDates.length - 1][1];
-      if (eventTimes.length == 0 || o.time != eventTimes[eventTimes.length - 1][0]) {
-        eventTimes.push([o.time, {}]);
+      if (eventTimes.length == 0 || time != eventTimes[eventTimes.length - 1][0]) {
+        eventTimes.push([time, {}, o]);
       }
 
       let eventVenues = eventTimes[eventTimes.length - 1][1];
@@ -132,7 +136,7 @@ class Schedule {
               m("h5.header", { key: d[0] + "Time" }, "Time"),
               ...venues.map(v => m("h5.header", { key: d[0] + v }, v)),
               ...d[1].map(e => [
-                m("div.time", { key: d[0] + e[0] }, e[0]),
+                m("div.time", { key: d[0] + e[0] }, printScheduleTime(e[2])),
                 ...venues.map(v => m("div.venue-events", { key: d[0] + e[0] + v }, [
                   m("div.venue", v),
                   e[1][v] ? m("ul", e[1][v].map(event => m(
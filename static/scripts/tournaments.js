@@ -63,14 +63,16 @@ const abbreviations = {
 };
 
 var tournamentListings = null;
+var warnings = [];
 var captcha = null;
 var error = false;
 
 fetch("/tournaments/fetch")
   .then(response => response.json())
   .then(data => {
-    if ("tournaments" in data) {
-      tournamentListings = data.tournaments;
+    if ("tournamentList" in data) {
+      tournamentListings = data.tournamentList.tournaments;
+      warnings = data.tournamentList.warnings;
     } else if ("captcha" in data) {
       captcha = data.captcha;
     } else {
@@ -222,14 +224,14 @@ class Main {
         if (this.locationMatches.length != 0) {
           list = list.filter(t => {
             for (let l of this.locationMatches) {
-              if (new RegExp(`\\b${l}\\b`).test(t.location)) {
+              if (t.location !== null && new RegExp(`\\b${l}\\b`).test(t.location)) {
                 return true;
               }
             }
             return false;
           });
         } else {
-          list = filterArray(this.locationFilter, list, t => t.location);
+          list = filterArray(this.locationFilter, list, t => t.location || "");
         }
       }
 
@@ -248,6 +250,7 @@ class Main {
     let pastTournaments = filteredTournaments.filter(isPast);
 
     return [
+      m(WarningNotice, { key: "warnings", warnings: warnings }),
       m("section#filter", { key: "filter"}, [
         m("h2", "Tournament Search"),
         m("label#name-filter-label", m("input#name-filter.filter", { type: "text", placeholder: "Filter by name" })),
@@ -299,7 +302,7 @@ class TournamentListing {
     let datesString = tournament.startDate != tournament.endDate ? `${printDate(tournament.startDate)} - ${printDate(tournament.endDate)}` : printDate(tournament.startDate);
     return m("div.tournament-listing", [
       m("h3.name", m("a", { href: `/tournament/${tournament.id}` }, m.trust(tournament.name))),
-      m("p.location", m.trust(tournament.location)),
+      tournament.location !== null ? m("p.location", m.trust(tournament.location)) : [],
       m("p.dates", datesString),
       m("div.logo", tournament.logoUrl !== null ? [m(LazyImage, { src: tournament.logoUrl })] : []),
       m(RegistrationStatus, { status: tournament.registrationStatus }),